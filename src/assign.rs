@@ -0,0 +1,208 @@
+use std::fmt;
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::char,
+    combinator::{not, opt, value},
+    sequence::{pair, terminated},
+    IResult,
+};
+use serde_json::Value;
+
+use crate::{
+    operators::{operate, parse_or, Sign},
+    parse::{ParseError, Parseable},
+    path::{getpath, paths, setpath, Path},
+    query::{Executable, Query},
+    scope::Scope,
+    single, space, truthy, QueryError, QueryResult,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AssignOp {
+    Set,
+    Update,
+    Arithmetic(Sign),
+    /// `//=`, which only replaces values that are `false` or `null`.
+    Alternative,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Assign {
+    pub left: Query,
+    pub op: AssignOp,
+    pub right: Query,
+}
+
+impl Executable for Assign {
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        let targets = paths(&self.left, value, scope)?;
+        match &self.op {
+            AssignOp::Set => self
+                .right
+                .execute_in(value, scope)?
+                .into_iter()
+                .map(|rv| update(value, &targets, |_| Ok(Some(rv.clone()))))
+                .collect(),
+            AssignOp::Update => single(update(value, &targets, |current| {
+                Ok(self.right.execute_in(current, scope)?.into_iter().next())
+            })?),
+            // The right hand side is evaluated against the original input, not the path's value
+            AssignOp::Arithmetic(sign) => self
+                .right
+                .execute_in(value, scope)?
+                .into_iter()
+                .map(|rv| {
+                    update(value, &targets, |current| {
+                        Ok(operate(sign, current, &rv)?.into_iter().next())
+                    })
+                })
+                .collect(),
+            AssignOp::Alternative => self
+                .right
+                .execute_in(value, scope)?
+                .into_iter()
+                .map(|rv| {
+                    update(value, &targets, |current| {
+                        if truthy(current) {
+                            Ok(Some(current.clone()))
+                        } else {
+                            Ok(Some(rv.clone()))
+                        }
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Replaces the value at each path with the result of `f` applied to its current value, leaving
+/// the value untouched where `f` produces nothing.
+fn update<F>(root: &Value, targets: &[(Path, Value)], f: F) -> Result<Value, QueryError>
+where
+    F: Fn(&Value) -> Result<Option<Value>, QueryError>,
+{
+    targets.iter().try_fold(root.clone(), |acc, (path, _)| {
+        match f(&getpath(&acc, path)?)? {
+            Some(vv) => setpath(&acc, path, vv),
+            None => Ok(acc),
+        }
+    })
+}
+
+impl fmt::Display for AssignOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssignOp::Set => write!(f, "="),
+            AssignOp::Update => write!(f, "|="),
+            AssignOp::Arithmetic(sign) => write!(f, "{}=", sign),
+            AssignOp::Alternative => write!(f, "//="),
+        }
+    }
+}
+
+impl fmt::Display for Assign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.left, self.op, self.right)
+    }
+}
+
+impl Parseable for AssignOp {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        space::around(alt((
+            value(AssignOp::Update, tag("|=")),
+            value(AssignOp::Arithmetic(Sign::Add), tag("+=")),
+            value(AssignOp::Arithmetic(Sign::Sub), tag("-=")),
+            value(AssignOp::Arithmetic(Sign::Mul), tag("*=")),
+            value(AssignOp::Alternative, tag("//=")),
+            value(AssignOp::Arithmetic(Sign::Div), tag("/=")),
+            value(AssignOp::Arithmetic(Sign::Mod), tag("%=")),
+            value(AssignOp::Set, terminated(char('='), not(char('=')))),
+        )))(input)
+    }
+}
+
+pub(crate) fn parse_assign(input: &str) -> IResult<&str, Query, ParseError> {
+    let (input, left) = parse_or(input)?;
+    let (input, opt) = opt(pair(AssignOp::parser, parse_or))(input)?;
+
+    if let Some((op, right)) = opt {
+        Ok((input, Query::Assign(Box::new(Assign { left, op, right }))))
+    } else {
+        Ok((input, left))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{combinator::Split, index::Index, raw::Raw};
+
+    use super::*;
+
+    #[test]
+    fn parse_assignment() {
+        assert!(Query::parse(".a =").is_err());
+        assert!(Query::parse("= 1").is_err());
+        assert!(Query::parse(".a = .b = 1").is_err());
+        assert!(!matches!(Query::parse(".a == 1"), Ok(Query::Assign(_))));
+
+        assert_eq!(
+            Query::Assign(Box::new(Assign {
+                left: Query::Index(Index::String("a".to_string())),
+                op: AssignOp::Set,
+                right: Query::Raw(Raw::parse("5").unwrap()),
+            })),
+            Query::parse(".a = 5").unwrap()
+        );
+        assert_eq!(
+            Query::Split(Box::new(Split(
+                Query::Assign(Box::new(Assign {
+                    left: Query::Index(Index::String("a".to_string())),
+                    op: AssignOp::Set,
+                    right: Query::Identity,
+                })),
+                Query::Identity
+            ))),
+            Query::parse(".a=.,.").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_update() {
+        assert!(Query::parse(".a |=").is_err());
+        assert!(Query::parse(".a + = 1").is_err());
+        assert!(Query::parse(".a += .b += 1").is_err());
+
+        assert_eq!(AssignOp::Update, AssignOp::parse(" |= ").unwrap());
+        assert_eq!(
+            AssignOp::Arithmetic(Sign::Add),
+            AssignOp::parse("+=").unwrap()
+        );
+        assert_eq!(
+            AssignOp::Arithmetic(Sign::Sub),
+            AssignOp::parse("-=").unwrap()
+        );
+        assert_eq!(
+            AssignOp::Arithmetic(Sign::Mul),
+            AssignOp::parse("*=").unwrap()
+        );
+        assert_eq!(
+            AssignOp::Arithmetic(Sign::Div),
+            AssignOp::parse("/=").unwrap()
+        );
+        assert_eq!(
+            AssignOp::Arithmetic(Sign::Mod),
+            AssignOp::parse("%=").unwrap()
+        );
+        assert_eq!(AssignOp::Alternative, AssignOp::parse("//=").unwrap());
+        assert_eq!(
+            Query::Assign(Box::new(Assign {
+                left: Query::Index(Index::String("a".to_string())),
+                op: AssignOp::Arithmetic(Sign::Add),
+                right: Query::Raw(Raw::parse("10").unwrap()),
+            })),
+            Query::parse(".a += 10").unwrap()
+        );
+    }
+}
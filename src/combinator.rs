@@ -1,3 +1,5 @@
+use std::fmt;
+
 use nom::{character::complete::char, combinator::opt, IResult};
 use serde_json::Value;
 
@@ -5,6 +7,7 @@ use crate::{
     empty,
     parse::{parse_chain, ParseError},
     query::{iterate_results, iterate_values, Executable, Query},
+    scope::Scope,
     QueryResult,
 };
 
@@ -12,8 +15,11 @@ use crate::{
 pub struct Split(pub Query, pub Query);
 
 impl Executable for Split {
-    fn execute(&self, value: &Value) -> QueryResult {
-        iterate_results(vec![self.0.execute(value), self.1.execute(value)])
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        iterate_results(vec![
+            self.0.execute_in(value, scope),
+            self.1.execute_in(value, scope),
+        ])
     }
 }
 
@@ -21,8 +27,8 @@ impl Executable for Split {
 pub struct Chain(pub Query, pub Query);
 
 impl Executable for Chain {
-    fn execute(&self, value: &Value) -> QueryResult {
-        iterate_values(self.0.execute(value)?.iter(), &self.1)
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        iterate_values(self.0.execute_in(value, scope)?.iter(), &self.1, scope)
     }
 }
 
@@ -30,14 +36,78 @@ impl Executable for Chain {
 pub struct Optional(pub Query);
 
 impl Executable for Optional {
-    fn execute(&self, value: &Value) -> QueryResult {
-        match self.0.execute(value) {
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        match self.0.execute_in(value, scope) {
             Ok(v) => Ok(v),
             Err(_) => empty(),
         }
     }
 }
 
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {}", self.0, self.1)
+    }
+}
+
+// Indexes and iterators are written directly after a query they can be chained onto, rather
+// than piped, so that a chain like `.a.b | .c` keeps its shape when parsed again
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if is_chain_head(&self.0) && is_chained(&self.1) {
+            write!(f, "{}", self.0)?;
+            fmt_chained(&self.1, f)
+        } else {
+            write!(f, "{} | {}", self.0, self.1)
+        }
+    }
+}
+
+fn is_chain_head(q: &Query) -> bool {
+    match q {
+        Query::Index(_)
+        | Query::Iterator
+        | Query::Contruct(_)
+        | Query::Function(_)
+        | Query::Variable(_)
+        | Query::Loc(_) => true,
+        Query::Optional(o) => is_chain_head(&o.0),
+        _ => false,
+    }
+}
+
+fn is_chained(q: &Query) -> bool {
+    match q {
+        Query::Index(_) | Query::Iterator => true,
+        Query::Optional(o) => matches!(o.0, Query::Index(_) | Query::Iterator),
+        // Chains nest to the right, so a chain on the left has to be piped
+        Query::Chain(c) => !matches!(c.0, Query::Chain(_)) && is_chained(&c.0) && is_chained(&c.1),
+        _ => false,
+    }
+}
+
+fn fmt_chained(q: &Query, f: &mut fmt::Formatter) -> fmt::Result {
+    match q {
+        Query::Index(i) => i.fmt_chained(f),
+        Query::Iterator => write!(f, "[]"),
+        Query::Optional(o) => {
+            fmt_chained(&o.0, f)?;
+            write!(f, "?")
+        }
+        Query::Chain(c) => {
+            fmt_chained(&c.0, f)?;
+            fmt_chained(&c.1, f)
+        }
+        _ => unreachable!(),
+    }
+}
+
+impl fmt::Display for Optional {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}?", self.0)
+    }
+}
+
 pub(crate) fn optional<'a, F>(
     mut f: F,
 ) -> impl FnMut(&'a str) -> IResult<&'a str, Query, ParseError>
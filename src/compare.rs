@@ -0,0 +1,167 @@
+use std::cmp::Ordering;
+
+use serde_json::{Map, Number, Value};
+
+use crate::{describe, QueryError};
+
+/// Orders values the way jq does: `null < false < true < numbers < strings < arrays < objects`.
+pub(crate) fn cmp_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(n), Value::Number(m)) => cmp_numbers(n, m),
+        (Value::String(s), Value::String(t)) => s.cmp(t),
+        (Value::Array(v), Value::Array(w)) => cmp_arrays(v, w),
+        (Value::Object(o), Value::Object(p)) => cmp_objects(o, p),
+        (v, w) => rank(v).cmp(&rank(w)),
+    }
+}
+
+fn rank(v: &Value) -> u8 {
+    match v {
+        Value::Null => 0,
+        Value::Bool(false) => 1,
+        Value::Bool(true) => 2,
+        Value::Number(_) => 3,
+        Value::String(_) => 4,
+        Value::Array(_) => 5,
+        Value::Object(_) => 6,
+    }
+}
+
+fn cmp_numbers(n: &Number, m: &Number) -> Ordering {
+    match (n.as_i64(), m.as_i64()) {
+        (Some(i), Some(j)) => i.cmp(&j),
+        _ => match (n.as_f64(), m.as_f64()) {
+            (Some(f), Some(g)) => f.partial_cmp(&g).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        },
+    }
+}
+
+pub(crate) fn cmp_arrays(v: &[Value], w: &[Value]) -> Ordering {
+    v.iter()
+        .zip(w)
+        .map(|(a, b)| cmp_values(a, b))
+        .find(|o| *o != Ordering::Equal)
+        .unwrap_or_else(|| v.len().cmp(&w.len()))
+}
+
+// Objects compare by their sorted key sets first, then value by value in key order
+fn cmp_objects(o: &Map<String, Value>, p: &Map<String, Value>) -> Ordering {
+    let mut ok: Vec<&String> = o.keys().collect();
+    let mut pk: Vec<&String> = p.keys().collect();
+    ok.sort();
+    pk.sort();
+    ok.cmp(&pk).then_with(|| {
+        ok.iter()
+            .map(|k| cmp_values(&o[k.as_str()], &p[k.as_str()]))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    })
+}
+
+/// Whether `a` contains `b` as jq defines it: objects contain every key of `b` with a value
+/// that contains `b`'s, arrays have an element containing each element of `b`, strings contain
+/// `b` as a substring, and anything else must be equal. Only the operands themselves must be of
+/// the same type; values of different types within them simply don't contain one another.
+pub(crate) fn contains(a: &Value, b: &Value) -> Result<bool, QueryError> {
+    if rank(a) == rank(b) || a.is_boolean() && b.is_boolean() {
+        Ok(contained(a, b))
+    } else {
+        Err(QueryError::Operation(
+            "check containment of",
+            describe(a),
+            describe(b),
+        ))
+    }
+}
+
+fn contained(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Object(o), Value::Object(p)) => p
+            .iter()
+            .all(|(k, bv)| matches!(o.get(k), Some(av) if contained(av, bv))),
+        (Value::Array(v), Value::Array(w)) => {
+            w.iter().all(|bv| v.iter().any(|av| contained(av, bv)))
+        }
+        (Value::String(s), Value::String(t)) => s.contains(t.as_str()),
+        (a, b) if rank(a) == rank(b) || a.is_boolean() && b.is_boolean() => {
+            cmp_values(a, b) == Ordering::Equal
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmp(a: &str, b: &str) -> Ordering {
+        cmp_values(
+            &serde_json::from_str(a).unwrap(),
+            &serde_json::from_str(b).unwrap(),
+        )
+    }
+
+    #[test]
+    fn type_order() {
+        let ordered = ["null", "false", "true", "-1", "\"\"", "[]", "{}"];
+        for (i, a) in ordered.iter().enumerate() {
+            for (j, b) in ordered.iter().enumerate() {
+                assert_eq!(i.cmp(&j), cmp(a, b));
+            }
+        }
+    }
+
+    #[test]
+    fn same_type_order() {
+        assert_eq!(Ordering::Less, cmp("1", "1.5"));
+        assert_eq!(Ordering::Equal, cmp("1", "1.0"));
+        assert_eq!(Ordering::Less, cmp("\"a\"", "\"b\""));
+        assert_eq!(Ordering::Less, cmp("[1,2]", "[1,2,0]"));
+        assert_eq!(Ordering::Greater, cmp("[1,3]", "[1,2,0]"));
+        assert_eq!(Ordering::Less, cmp(r#"{"a":2}"#, r#"{"b":1}"#));
+        assert_eq!(Ordering::Less, cmp(r#"{"a":1,"b":2}"#, r#"{"a":1,"b":3}"#));
+    }
+
+    #[test]
+    fn containment() {
+        let check = |a: &str, b: &str| {
+            contains(
+                &serde_json::from_str(a).unwrap(),
+                &serde_json::from_str(b).unwrap(),
+            )
+        };
+        assert!(check(r#"{"a":{"b":1,"c":2}}"#, r#"{"a":{"b":1}}"#).unwrap());
+        assert!(!check(r#"{"a":{"b":1}}"#, r#"{"a":{"b":1,"c":2}}"#).unwrap());
+        assert!(!check(r#"{"a":1}"#, r#"{"b":null}"#).unwrap());
+        assert!(check(r#"["foobar","baz"]"#, r#"["baz","bar","baz"]"#).unwrap());
+        assert!(!check(r#"["foobar","baz"]"#, r#"["qux"]"#).unwrap());
+        assert!(check("[[1,2],3]", "[[2]]").unwrap());
+        assert!(check("[]", "[]").unwrap());
+        assert!(check("[1]", "[]").unwrap());
+        assert!(check(r#""foobar""#, r#""bar""#).unwrap());
+        assert!(check(r#""foobar""#, r#""""#).unwrap());
+        assert!(!check(r#""bar""#, r#""foobar""#).unwrap());
+        assert!(check("1", "1.0").unwrap());
+        assert!(!check("1", "2").unwrap());
+        assert!(check("true", "true").unwrap());
+        assert!(!check("true", "false").unwrap());
+        assert!(check("null", "null").unwrap());
+
+        assert!(check(r#"{"a":1}"#, "[1]").is_err());
+        assert!(check("[1]", "1").is_err());
+        assert!(check(r#"[1,"a"]"#, r#"["a"]"#).unwrap());
+        assert!(!check(r#"{"a":1}"#, r#"{"a":"x"}"#).unwrap());
+        assert!(!check(r#"[[1]]"#, r#"[{"a":1}]"#).unwrap());
+        assert!(check("1", r#""1""#).is_err());
+    }
+
+    #[test]
+    fn mixed_numbers() {
+        assert_eq!(Ordering::Equal, cmp("1", "1.0"));
+        assert_eq!(Ordering::Equal, cmp("-0.0", "0"));
+        assert_eq!(Ordering::Less, cmp("1", "1.5"));
+        assert_eq!(Ordering::Greater, cmp("2.5", "2"));
+        assert_eq!(Ordering::Equal, cmp("[1, 2.0]", "[1.0, 2]"));
+    }
+}
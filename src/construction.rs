@@ -1,20 +1,23 @@
+use std::fmt;
+
 use crate::{
+    describe,
     index::Index,
     parse::{parse_init, parse_pipe, ParseError, Parseable},
     query::{Executable, Query},
-    space, type_str, QueryError, QueryResult,
+    scope::Scope,
+    single, space, string, QueryError, QueryResult,
 };
 use itertools::Itertools;
 use nom::{
     branch::alt,
-    bytes::complete::take_while1,
     character::complete::{alphanumeric1, char},
-    combinator::map,
+    combinator::{cut, map, opt},
     multi::separated_list0,
-    sequence::{delimited, separated_pair},
+    sequence::{delimited, preceded, separated_pair, terminated},
     IResult,
 };
-use serde_json::Value;
+use serde_json::{Map, Value};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Construct {
@@ -29,15 +32,15 @@ pub enum Key {
 }
 
 impl Key {
-    fn execute(&self, value: &Value) -> Result<Vec<String>, QueryError> {
+    fn execute(&self, value: &Value, scope: &Scope) -> Result<Vec<String>, QueryError> {
         let keys = match self {
             Key::Simple(s) => vec![s.clone()],
             Key::Query(inner) => {
                 let mut keys = Vec::new();
-                for k in inner.execute(value)? {
+                for k in inner.execute_in(value, scope)? {
                     match k {
                         Value::String(s) => keys.push(s),
-                        vv => return Err(QueryError::ObjectKey(type_str(&vv))),
+                        vv => return Err(QueryError::ObjectKey(describe(&vv))),
                     }
                 }
                 keys
@@ -56,32 +59,77 @@ impl Construct {
 }
 
 impl Executable for Construct {
-    fn execute(&self, value: &Value) -> QueryResult {
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
         match self {
-            Construct::Array(inner) => construct_array(value, inner),
-            Construct::Object(kvs) => construct_object(value, kvs),
+            Construct::Array(inner) => construct_array(value, inner, scope),
+            Construct::Object(kvs) => construct_object(value, kvs, scope),
         }
     }
 }
 
-fn construct_array(v: &Value, inner: &Query) -> QueryResult {
-    Ok(vec![Value::Array(inner.execute(v)?)])
+fn construct_array(v: &Value, inner: &Query, scope: &Scope) -> QueryResult {
+    Ok(vec![Value::Array(inner.execute_in(v, scope)?)])
 }
 
-fn construct_object(value: &Value, kvs: &[(Key, Query)]) -> QueryResult {
-    Ok(kvs
+fn construct_object(value: &Value, kvs: &[(Key, Query)], scope: &Scope) -> QueryResult {
+    // The product of no pairs would otherwise be no objects rather than one empty object
+    if kvs.is_empty() {
+        return single(Value::Object(Map::new()));
+    }
+
+    let pairs = kvs
         .iter()
-        .map(|(k, v)| (k.execute(value), v.execute(value)))
+        .map(|(k, v)| (k.execute(value, scope), v.execute_in(value, scope)))
         .map(|(kr, vr)| kr.and_then(|ks| vr.map(|vs| (ks, vs))))
-        .collect::<Result<Vec<(Vec<String>, Vec<Value>)>, _>>()? // Unwrap pairs of results into just pairs of vectors
+        .collect::<Result<Vec<(Vec<String>, Vec<Value>)>, _>>()?; // Unwrap pairs of results into just pairs of vectors
+
+    if let Some(limit) = scope.options().max_combinations {
+        let total = pairs.iter().fold(1usize, |acc, (ks, vs)| {
+            acc.saturating_mul(ks.len().saturating_mul(vs.len()))
+        });
+        if total > limit {
+            return Err(QueryError::TooManyCombinations(total, limit));
+        }
+    }
+
+    Ok(pairs
         .into_iter() // At this point, each of key and value might have been evaluated to to many values
         .map(|(ks, vs)| ks.into_iter().cartesian_product(vs)) // Get all combinations for each pair
         .multi_cartesian_product() // Get all combinations of different pairs
-        .map(|pairs| pairs.into_iter().collect())
+        .map(|mut pairs| {
+            // Sorting is stable, so the last of any duplicate keys still wins
+            pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+            pairs.into_iter().collect()
+        })
         .map(Value::Object)
         .collect())
 }
 
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Simple(s) if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                write!(f, "{}", s)
+            }
+            Key::Simple(s) => write!(f, "{}", Value::from(s.as_str())),
+            Key::Query(q) => write!(f, "({})", q),
+        }
+    }
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Construct::Array(inner) => write!(f, "[{}]", inner),
+            Construct::Object(kvs) => write!(
+                f,
+                "{{{}}}",
+                kvs.iter().map(|(k, v)| format!("{}: {}", k, v)).join(", ")
+            ),
+        }
+    }
+}
+
 impl Parseable for Construct {
     fn parser(input: &str) -> IResult<&str, Construct, ParseError> {
         alt((parse_array, parse_object))(input)
@@ -89,41 +137,45 @@ impl Parseable for Construct {
 }
 
 fn parse_array(input: &str) -> IResult<&str, Construct, ParseError> {
-    let (input, inner) = delimited(char('['), space::around(parse_pipe), char(']'))(input)?;
-    Ok((input, Construct::Array(Box::new(inner))))
+    // Nothing else starts with a bracket, so a failure inside it is reported where it happened
+    let (input, inner) = preceded(
+        char('['),
+        cut(terminated(space::around(opt(parse_pipe)), char(']'))),
+    )(input)?;
+    Ok((
+        input,
+        Construct::Array(Box::new(inner.unwrap_or(Query::Empty))),
+    ))
 }
 
 fn parse_object(input: &str) -> IResult<&str, Construct, ParseError> {
-    let (input, kvs) = delimited(
+    let (input, kvs) = preceded(
         char('{'),
-        space::around(separated_list0(
-            char(','),
-            space::around(alt((
-                separated_pair(
-                    alt((
-                        map(delimited(char('('), parse_init, char(')')), Key::Query),
-                        map(
-                            alt((
-                                alphanumeric1,
-                                delimited(char('"'), take_while1(|c| c != '"'), char('"')),
-                            )),
-                            |s: &str| Key::Simple(s.to_string()),
-                        ),
-                    )),
-                    space::around(char(':')),
-                    parse_init,
-                ),
-                map(alphanumeric1, |s: &str| Construct::shorthand(s.to_string())),
-            ))),
+        cut(terminated(
+            space::around(separated_list0(
+                char(','),
+                space::around(alt((
+                    separated_pair(
+                        alt((
+                            map(delimited(char('('), parse_init, char(')')), Key::Query),
+                            map(alphanumeric1, |s: &str| Key::Simple(s.to_string())),
+                            map(string::json_string, Key::Simple),
+                        )),
+                        space::around(char(':')),
+                        cut(parse_init),
+                    ),
+                    map(alphanumeric1, |s: &str| Construct::shorthand(s.to_string())),
+                ))),
+            )),
+            char('}'),
         )),
-        char('}'),
     )(input)?;
     Ok((input, Construct::Object(kvs)))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::combinator::Split;
+    use crate::{combinator::Split, scope::ExecOptions};
 
     use super::*;
 
@@ -132,8 +184,15 @@ mod tests {
         assert!(Construct::parse("[").is_err());
         assert!(Construct::parse("]").is_err());
         assert!(Construct::parse("].[").is_err());
-        assert!(Construct::parse("[]").is_err()); // TODO: Probably should be allowed
 
+        assert_eq!(
+            Construct::Array(Box::new(Query::Empty)),
+            Construct::parse("[]").unwrap()
+        );
+        assert_eq!(
+            Construct::Array(Box::new(Query::Empty)),
+            Construct::parse("[ ]").unwrap()
+        );
         assert_eq!(
             Construct::Array(Box::new(Query::Identity)),
             Construct::parse("[.]").unwrap()
@@ -174,4 +233,28 @@ mod tests {
             Construct::parse("{foo,bar:.bar,(.baz):.[]}").unwrap()
         );
     }
+
+    #[test]
+    fn combination_limit() {
+        let q = Construct::parse("{a: .[], b: .[]}").unwrap();
+        let v: Value = serde_json::from_str("[1,2,3]").unwrap();
+
+        let options = ExecOptions {
+            max_combinations: Some(4),
+            ..ExecOptions::default()
+        };
+        let scope = Scope::new().with_options(options);
+        assert!(matches!(
+            q.execute_in(&v, &scope),
+            Err(QueryError::TooManyCombinations(9, 4))
+        ));
+
+        let options = ExecOptions {
+            max_combinations: Some(9),
+            ..ExecOptions::default()
+        };
+        let scope = Scope::new().with_options(options);
+        assert_eq!(9, q.execute_in(&v, &scope).unwrap().len());
+        assert_eq!(9, q.execute(&v).unwrap().len());
+    }
 }
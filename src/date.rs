@@ -0,0 +1,163 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike, Utc,
+};
+use serde_json::Value;
+use std::convert::TryFrom;
+
+use crate::{type_str, QueryError};
+
+/// The format used by `todate` and `fromdate`.
+pub(crate) const ISO_8601: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// The current time in seconds since the epoch, with fractional seconds.
+pub(crate) fn now() -> Value {
+    let t = Utc::now();
+    Value::from(t.timestamp() as f64 + f64::from(t.timestamp_subsec_nanos()) / 1e9)
+}
+
+/// Formats a time given in seconds since the epoch or broken down, which is always in UTC.
+pub(crate) fn strftime(v: &Value, fmt: &Value) -> Result<Value, QueryError> {
+    let t = time(v)?;
+    let fmt = format_str(fmt)?;
+    Ok(Value::String(
+        t.format_with_items(items(fmt)?.into_iter()).to_string(),
+    ))
+}
+
+/// Parses a time in the given format into jq's broken down time. Times without an offset are
+/// taken to be in UTC.
+pub(crate) fn strptime(v: &Value, fmt: &Value) -> Result<Value, QueryError> {
+    Ok(broken_down(&parse(v, fmt)?))
+}
+
+/// Breaks down a time given in seconds since the epoch.
+pub(crate) fn gmtime(v: &Value) -> Result<Value, QueryError> {
+    match v {
+        Value::Number(_) => Ok(broken_down(&time(v)?)),
+        vv => Err(QueryError::Unsupported(
+            type_str(vv),
+            "broken down as a time",
+        )),
+    }
+}
+
+/// Converts a broken down time into whole seconds since the epoch.
+pub(crate) fn mktime(v: &Value) -> Result<Value, QueryError> {
+    match v {
+        Value::Array(arr) => Ok(Value::from(from_broken_down(arr)?.timestamp())),
+        vv => Err(QueryError::Unsupported(
+            type_str(vv),
+            "used as a broken down time",
+        )),
+    }
+}
+
+/// Parses a time in the given format into seconds since the epoch.
+pub(crate) fn fromdate(v: &Value, fmt: &Value) -> Result<Value, QueryError> {
+    Ok(Value::from(parse(v, fmt)?.timestamp()))
+}
+
+fn parse(v: &Value, fmt: &Value) -> Result<DateTime<Utc>, QueryError> {
+    let s = match v {
+        Value::String(s) => s,
+        vv => return Err(QueryError::Unsupported(type_str(vv), "parsed as a date")),
+    };
+    let fmt = format_str(fmt)?;
+    items(fmt)?;
+
+    DateTime::parse_from_str(s, fmt)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, fmt).map(|t| t.and_utc()))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(s, fmt).map(|d| d.and_time(Default::default()).and_utc())
+        })
+        .map_err(|_| QueryError::Date(s.clone(), fmt.to_string()))
+}
+
+fn time(v: &Value) -> Result<DateTime<Utc>, QueryError> {
+    let secs = match v {
+        Value::Number(n) => n.as_f64().unwrap_or_default(),
+        Value::Array(arr) => return from_broken_down(arr),
+        vv => return Err(QueryError::Unsupported(type_str(vv), "formatted as a date")),
+    };
+    let whole = secs.floor();
+    let nanos = ((secs - whole) * 1e9) as u32;
+    if whole < i64::MIN as f64 || whole >= i64::MAX as f64 {
+        return Err(QueryError::Numerical);
+    }
+    DateTime::from_timestamp(whole as i64, nanos).ok_or(QueryError::Numerical)
+}
+
+// As with C's `timegm`, fields out of their usual range carry over into the next field up,
+// and the day of the week and of the year are ignored
+fn from_broken_down(arr: &[Value]) -> Result<DateTime<Utc>, QueryError> {
+    let fields = arr
+        .iter()
+        .take(6)
+        .map(|vv| match vv {
+            Value::Number(n) => Ok(n.as_f64().unwrap_or_default().floor()),
+            vv => Err(QueryError::Unsupported(
+                type_str(vv),
+                "used in a broken down time",
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (year, month, day, hours, minutes, seconds) = match fields.as_slice() {
+        [y, mo, d, h, mi, s] => (*y as i64, *mo as i64, *d as i64, *h, *mi, *s),
+        _ => {
+            return Err(QueryError::Custom(
+                "A broken down time requires an array of at least 6 numbers".to_string(),
+            ))
+        }
+    };
+
+    let year = year
+        .checked_add(month.div_euclid(12))
+        .and_then(|y| i32::try_from(y).ok())
+        .ok_or(QueryError::Numerical)?;
+    let month = month.rem_euclid(12) as u32 + 1;
+    let first = NaiveDate::from_ymd_opt(year, month, 1).ok_or(QueryError::Numerical)?;
+    let secs = (day - 1) as f64 * 86400.0 + hours * 3600.0 + minutes * 60.0 + seconds;
+    let midnight = first.and_time(Default::default()).and_utc().timestamp() as f64;
+    time(&Value::from(midnight + secs))
+}
+
+/// Times broken down as in jq: the year, the month counting from 0, the day of the month, the
+/// hours, minutes and seconds, the day of the week counting from Sunday, and the day of the
+/// year counting from 0.
+fn broken_down(t: &DateTime<Utc>) -> Value {
+    let secs = match t.nanosecond() {
+        0 => Value::from(t.second()),
+        n => Value::from(f64::from(t.second()) + f64::from(n) / 1e9),
+    };
+    Value::Array(vec![
+        Value::from(t.year()),
+        Value::from(t.month0()),
+        Value::from(t.day()),
+        Value::from(t.hour()),
+        Value::from(t.minute()),
+        secs,
+        Value::from(t.weekday().num_days_from_sunday()),
+        Value::from(t.ordinal0()),
+    ])
+}
+
+fn format_str(fmt: &Value) -> Result<&str, QueryError> {
+    match fmt {
+        Value::String(s) => Ok(s),
+        vv => Err(QueryError::Unsupported(
+            type_str(vv),
+            "used as a date format",
+        )),
+    }
+}
+
+// Formatting with an invalid specifier would panic, so formats are checked up front
+fn items(fmt: &str) -> Result<Vec<Item<'_>>, QueryError> {
+    let items: Vec<_> = StrftimeItems::new(fmt).collect();
+    if items.contains(&Item::Error) {
+        return Err(QueryError::DateFormat(fmt.to_string()));
+    }
+    Ok(items)
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+
+use nom::{branch::alt, character::complete::char, combinator::value, sequence::preceded, IResult};
+use serde_json::Value;
+
+use crate::{
+    parse::{keyword, ParseError, Parseable},
+    query::Executable,
+    scope::Scope,
+    single, type_str, QueryError, QueryResult,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Format {
+    Html,
+    Uri,
+    Sh,
+    Base32,
+    Base32d,
+    Json,
+}
+
+impl Executable for Format {
+    fn execute_in(&self, value: &Value, _: &Scope) -> QueryResult {
+        let s = match self {
+            Format::Html => html(&text(value)),
+            Format::Uri => uri(&text(value)),
+            Format::Sh => sh(value)?,
+            Format::Base32 => base32(text(value).as_bytes()),
+            Format::Base32d => base32d(&text(value))?,
+            Format::Json => value.to_string(),
+        };
+        single(Value::String(s))
+    }
+}
+
+// Strings are formatted as they are, anything else as its JSON text
+pub(crate) fn text(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        vv => vv.to_string(),
+    }
+}
+
+fn html(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '&' => res.push_str("&amp;"),
+            '\'' => res.push_str("&#39;"),
+            '"' => res.push_str("&quot;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+// Everything except the unreserved characters of RFC 3986 is percent-encoded
+fn uri(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                res.push(b as char)
+            }
+            b => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
+
+fn sh(v: &Value) -> Result<String, QueryError> {
+    let words = match v {
+        Value::Array(arr) => arr.iter().map(sh_word).collect::<Result<Vec<_>, _>>()?,
+        vv => vec![sh_word(vv)?],
+    };
+    Ok(words.join(" "))
+}
+
+fn sh_word(v: &Value) -> Result<String, QueryError> {
+    match v {
+        Value::String(s) => Ok(format!("'{}'", s.replace('\'', "'\\''"))),
+        Value::Array(_) | Value::Object(_) => {
+            Err(QueryError::Unsupported(type_str(v), "escaped for shell"))
+        }
+        vv => Ok(vv.to_string()),
+    }
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// The standard alphabet of RFC 4648, padded to a multiple of 8 characters
+fn base32(bytes: &[u8]) -> String {
+    let mut res = String::with_capacity((bytes.len() + 4) / 5 * 8);
+    for chunk in bytes.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = buf.iter().fold(0u64, |acc, b| acc << 8 | u64::from(*b));
+        let chars = (chunk.len() * 8 + 4) / 5;
+        for i in 0..8 {
+            if i < chars {
+                let index = (bits >> (35 - i * 5)) & 0x1f;
+                res.push(BASE32_ALPHABET[index as usize] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+// Padding is optional, and any bits left over at the end are dropped
+fn base32d(s: &str) -> Result<String, QueryError> {
+    let mut bytes = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let index = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .ok_or_else(|| QueryError::Custom(format!("{:?} is not valid base32 data", s)))?;
+        bits = bits << 5 | index as u32;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Html => "html",
+            Format::Uri => "uri",
+            Format::Sh => "sh",
+            Format::Base32 => "base32",
+            Format::Base32d => "base32d",
+            Format::Json => "json",
+        };
+        write!(f, "@{}", name)
+    }
+}
+
+impl Parseable for Format {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        preceded(
+            char('@'),
+            alt((
+                value(Format::Html, keyword("html")),
+                value(Format::Uri, keyword("uri")),
+                value(Format::Sh, keyword("sh")),
+                value(Format::Base32d, keyword("base32d")),
+                value(Format::Base32, keyword("base32")),
+                value(Format::Json, keyword("json")),
+            )),
+        )(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_format() {
+        assert!(Format::parse("html").is_err());
+        assert!(Format::parse("@ html").is_err());
+        assert!(Format::parse("@htmls").is_err());
+        assert!(Format::parse("@unknown").is_err());
+
+        assert_eq!(Format::Html, Format::parse("@html").unwrap());
+        assert_eq!(Format::Uri, Format::parse("@uri").unwrap());
+        assert_eq!(Format::Sh, Format::parse("@sh").unwrap());
+        assert_eq!(Format::Base32, Format::parse("@base32").unwrap());
+        assert_eq!(Format::Base32d, Format::parse("@base32d").unwrap());
+        assert_eq!(Format::Json, Format::parse("@json").unwrap());
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        let cases = [
+            ("", ""),
+            ("f", "MY======"),
+            ("fo", "MZXQ===="),
+            ("foo", "MZXW6==="),
+            ("foob", "MZXW6YQ="),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI======"),
+            ("hello", "NBSWY3DP"),
+        ];
+        for (decoded, encoded) in cases {
+            assert_eq!(encoded, base32(decoded.as_bytes()));
+            assert_eq!(decoded, base32d(encoded).unwrap());
+        }
+        assert_eq!("foob", base32d("MZXW6YQ").unwrap());
+        assert!(base32d("mzxw6yq=").is_err());
+        assert!(base32d("MZXW1").is_err());
+    }
+}
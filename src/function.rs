@@ -0,0 +1,869 @@
+use std::{cmp::Ordering, fmt, iter};
+
+use itertools::{iproduct, Itertools};
+use nom::{
+    character::complete::char,
+    combinator::{map, opt},
+    multi::separated_list1,
+    sequence::{delimited, pair},
+    IResult,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    compare::{cmp_arrays, cmp_values, contains},
+    date, describe, empty, format,
+    index::Index,
+    matching::Pattern,
+    null,
+    operators::merge,
+    parse::{parse_identifier, parse_pipe, ParseError, Parseable},
+    path::{delpaths, getpath, paths, setpath, Path},
+    query::{into_iter, iterate_results, Descendants, Executable, Query, QueryIter},
+    scope::Scope,
+    single, sorted_entries, space, truthy, type_str, QueryError, QueryResult,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub args: Vec<Query>,
+}
+
+impl Function {
+    pub fn new(name: &str, args: Vec<Query>) -> Self {
+        Function {
+            name: name.to_string(),
+            args,
+        }
+    }
+}
+
+impl Executable for Function {
+    // Remaining inputs are read one at a time, so that they're never all held at once. Ranges
+    // and other generators are produced one at a time too, as they can be far too long, or even
+    // endless, when only the first few results are wanted.
+    fn execute_iter_in<'a>(&'a self, value: Value, scope: Scope) -> QueryIter<'a> {
+        match (self.name.as_str(), self.args.as_slice()) {
+            ("inputs", []) => Box::new(iter::from_fn(move || scope.next_input())),
+            ("range", [_]) | ("range", [_, _]) | ("range", [_, _, _]) => {
+                range(&value, &self.args, &scope)
+            }
+            ("while", [cond, update]) => unfold(value, move |vv| {
+                let mut steps = Vec::new();
+                for c in cond.execute_in(vv, &scope)? {
+                    if truthy(&c) {
+                        steps.push(Step::Emit(vv.clone()));
+                        steps.extend(update.execute_in(vv, &scope)?.into_iter().map(Step::Visit));
+                    }
+                }
+                Ok(steps)
+            }),
+            ("until", [cond, next]) => unfold(value, move |vv| {
+                let mut steps = Vec::new();
+                for c in cond.execute_in(vv, &scope)? {
+                    if truthy(&c) {
+                        steps.push(Step::Emit(vv.clone()));
+                    } else {
+                        steps.extend(next.execute_in(vv, &scope)?.into_iter().map(Step::Visit));
+                    }
+                }
+                Ok(steps)
+            }),
+            ("repeat", [f]) | ("recurse", [f]) => unfold(value, move |vv| {
+                let mut steps = vec![Step::Emit(vv.clone())];
+                steps.extend(f.execute_in(vv, &scope)?.into_iter().map(Step::Visit));
+                Ok(steps)
+            }),
+            ("recurse", [f, cond]) => unfold(value, move |vv| {
+                let mut steps = vec![Step::Emit(vv.clone())];
+                for next in f.execute_in(vv, &scope)? {
+                    for c in cond.execute_in(&next, &scope)? {
+                        if truthy(&c) {
+                            steps.push(Step::Visit(next.clone()));
+                        }
+                    }
+                }
+                Ok(steps)
+            }),
+            ("recurse", []) => Box::new(Descendants::new(value).map(Ok)),
+            ("limit", [n, f]) => match n.execute_in(&value, &scope) {
+                Ok(ns) => Box::new(
+                    ns.into_iter()
+                        .flat_map(move |nn| limit(value.clone(), &nn, f, scope.clone())),
+                ),
+                Err(e) => Box::new(iter::once(Err(e))),
+            },
+            _ => into_iter(self.execute_in(&value, &scope)),
+        }
+    }
+
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        match (self.name.as_str(), self.args.as_slice()) {
+            ("empty", []) => empty(),
+            ("debug", []) => {
+                scope.message(&format!("{}\n", json!(["DEBUG:", value])));
+                single(value.clone())
+            }
+            ("stderr", []) => {
+                scope.message(&value.to_string());
+                single(value.clone())
+            }
+            ("input", []) => match scope.next_input() {
+                Some(input) => single(input?),
+                None => Err(QueryError::NoMoreInputs),
+            },
+            ("inputs", []) => iter::from_fn(|| scope.next_input()).collect(),
+            ("env", []) => match scope.get("ENV") {
+                Some(env) => single(env.clone()),
+                None => single(Value::Object(Map::new())),
+            },
+            ("error", []) => Err(error(value)),
+            ("error", [msg]) => match msg.execute_in(value, scope)?.first() {
+                Some(m) => Err(error(m)),
+                None => empty(),
+            },
+            ("sort", []) => sort(value),
+            ("sort_by", [f]) => sort_by(value, f, scope),
+            ("min", []) => min_by(value, &Query::Identity, scope),
+            ("max", []) => max_by(value, &Query::Identity, scope),
+            ("min_by", [f]) => min_by(value, f, scope),
+            ("max_by", [f]) => max_by(value, f, scope),
+            ("indices", [x]) => positions(value, x, scope, |found| {
+                Value::Array(found.into_iter().map(Value::from).collect())
+            }),
+            ("index", [x]) => positions(value, x, scope, |found| {
+                found.first().map_or(Value::Null, |i| Value::from(*i))
+            }),
+            ("rindex", [x]) => positions(value, x, scope, |found| {
+                found.last().map_or(Value::Null, |i| Value::from(*i))
+            }),
+            ("split", [sep]) => iterate_results(
+                sep.execute_in(value, scope)?
+                    .iter()
+                    .map(|s| split(value, s)),
+            ),
+            ("split", [re, flags]) => with_pattern(value, re, Some(flags), scope, |s, p| {
+                single(Value::Array(p.split(s)))
+            }),
+            ("test", [re]) => with_pattern(value, re, None, scope, test),
+            ("test", [re, flags]) => with_pattern(value, re, Some(flags), scope, test),
+            ("capture", [re]) => with_pattern(value, re, None, scope, capture),
+            ("capture", [re, flags]) => with_pattern(value, re, Some(flags), scope, capture),
+            ("scan", [re]) => with_pattern(value, re, None, scope, |s, p| Ok(p.scan(s))),
+            ("scan", [re, flags]) => {
+                with_pattern(value, re, Some(flags), scope, |s, p| Ok(p.scan(s)))
+            }
+            ("splits", [re]) => with_pattern(value, re, None, scope, |s, p| Ok(p.split(s))),
+            ("splits", [re, flags]) => {
+                with_pattern(value, re, Some(flags), scope, |s, p| Ok(p.split(s)))
+            }
+            ("group_by", [f]) => group_by(value, f, scope),
+            ("reverse", []) => reverse(value),
+            ("first", []) => Index::Integer(0).execute_in(value, scope),
+            ("first", [f]) => f
+                .execute_iter_in(value.clone(), scope.clone())
+                .take(1)
+                .collect(),
+            ("last", []) => Index::Integer(-1).execute_in(value, scope),
+            ("last", [f]) => Ok(f.execute_in(value, scope)?.pop().into_iter().collect()),
+            ("range", [_])
+            | ("range", [_, _])
+            | ("range", [_, _, _])
+            | ("while", [_, _])
+            | ("until", [_, _])
+            | ("repeat", [_])
+            | ("recurse", [])
+            | ("recurse", [_])
+            | ("recurse", [_, _])
+            | ("limit", [_, _]) => self.execute_iter_in(value.clone(), scope.clone()).collect(),
+            ("nth", [n]) => iterate_results(
+                n.execute_in(value, scope)?
+                    .iter()
+                    .map(|nn| Index::Integer(index(nn, value)?).execute_in(value, scope)),
+            ),
+            ("nth", [n, f]) => iterate_results(
+                n.execute_in(value, scope)?
+                    .iter()
+                    .map(|nn| nth(value, index(nn, value)?, f, scope)),
+            ),
+            ("paths", []) => all_paths(value, scope, |_| Ok(1)),
+            ("paths", [f]) => all_paths(value, scope, |vv| {
+                Ok(f.execute_in(vv, scope)?
+                    .iter()
+                    .filter(|c| truthy(c))
+                    .count())
+            }),
+            ("getpath", [p]) => iterate_results(
+                p.execute_in(value, scope)?
+                    .iter()
+                    .map(|pp| getpath(value, path_arg(pp)?).map(|vv| vec![vv])),
+            ),
+            ("delpaths", [ps]) => iterate_results(ps.execute_in(value, scope)?.iter().map(|pps| {
+                let ps = match pps {
+                    Value::Array(ps) => ps.iter().map(path_arg).collect::<Result<Vec<_>, _>>()?,
+                    vv => return Err(QueryError::Unsupported(type_str(vv), "used as paths")),
+                };
+                single(delpaths(value, &ps)?)
+            })),
+            ("del", [f]) => {
+                let targets = paths(f, value, scope)?;
+                let ps: Vec<_> = targets.iter().map(|(p, _)| p.as_slice()).collect();
+                single(delpaths(value, &ps)?)
+            }
+            // Only the selected paths are copied over, so everything else is left null
+            ("pick", [f]) => {
+                let mut picked = Value::Null;
+                for (p, vv) in paths(f, value, scope)? {
+                    picked = setpath(&picked, &p, vv)?;
+                }
+                single(picked)
+            }
+            ("leaf_paths", []) => all_paths(value, scope, |vv| {
+                Ok(usize::from(!matches!(
+                    vv,
+                    Value::Array(_) | Value::Object(_)
+                )))
+            }),
+            ("tostream", []) => tostream(value, scope),
+            ("fromstream", [f]) => fromstream(&f.execute_in(value, scope)?),
+            ("now", []) => single(date::now()),
+            ("todate", []) => single(date::strftime(value, &json!(date::ISO_8601))?),
+            ("fromdate", []) => single(date::fromdate(value, &json!(date::ISO_8601))?),
+            ("gmtime", []) => single(date::gmtime(value)?),
+            ("mktime", []) => single(date::mktime(value)?),
+            ("strftime", [fmt]) => iterate_results(
+                fmt.execute_in(value, scope)?
+                    .iter()
+                    .map(|f| date::strftime(value, f).map(|s| vec![s])),
+            ),
+            ("strptime", [fmt]) => iterate_results(
+                fmt.execute_in(value, scope)?
+                    .iter()
+                    .map(|f| date::strptime(value, f).map(|t| vec![t])),
+            ),
+            ("length", []) => length(value),
+            ("ascii_downcase", []) => ascii_case(value, str::to_ascii_lowercase, "lowercased"),
+            ("ascii_upcase", []) => ascii_case(value, str::to_ascii_uppercase, "uppercased"),
+            ("ltrimstr", [affix]) => trimstr(value, affix, scope, |s, a| s.strip_prefix(a)),
+            ("rtrimstr", [affix]) => trimstr(value, affix, scope, |s, a| s.strip_suffix(a)),
+            ("keys", []) => keys(value),
+            ("has", [key]) => iterate_results(
+                key.execute_in(value, scope)?
+                    .iter()
+                    .map(|k| has_key(value, k).map(|b| vec![Value::Bool(b)])),
+            ),
+            // The input is the key and the argument is the container it's looked up in
+            ("in", [container]) => iterate_results(
+                container
+                    .execute_in(value, scope)?
+                    .iter()
+                    .map(|c| has_key(c, value).map(|b| vec![Value::Bool(b)])),
+            ),
+            ("tostring", []) => single(Value::String(format::text(value))),
+            ("tojson", []) => single(Value::String(value.to_string())),
+            ("nulls", []) => pass_if(value, value.is_null()),
+            ("booleans", []) => pass_if(value, value.is_boolean()),
+            ("numbers", []) => pass_if(value, value.is_number()),
+            ("strings", []) => pass_if(value, value.is_string()),
+            ("arrays", []) => pass_if(value, value.is_array()),
+            ("objects", []) => pass_if(value, value.is_object()),
+            ("iterables", []) => pass_if(value, value.is_array() || value.is_object()),
+            ("scalars", []) => pass_if(value, !(value.is_array() || value.is_object())),
+            ("values", []) => pass_if(value, !value.is_null()),
+            ("abs", []) => abs(value),
+            ("toarray", []) => match value {
+                Value::Array(_) => single(value.clone()),
+                vv => single(Value::Array(vec![vv.clone()])),
+            },
+            ("contains", [b]) => iterate_results(
+                b.execute_in(value, scope)?
+                    .iter()
+                    .map(|bv| contains(value, bv).map(|c| vec![Value::Bool(c)])),
+            ),
+            ("inside", [a]) => iterate_results(
+                a.execute_in(value, scope)?
+                    .iter()
+                    .map(|av| contains(av, value).map(|c| vec![Value::Bool(c)])),
+            ),
+            // A recursive merge, as in `. * $other`
+            ("merge", [other]) => iterate_results(
+                other
+                    .execute_in(value, scope)?
+                    .iter()
+                    .map(|o| merge(value, o, "merge").map(|m| vec![m])),
+            ),
+            ("infinite", []) => single(Value::from(f64::MAX)),
+            ("nan", []) => null(),
+            ("isinfinite", []) => {
+                float(value).and_then(|f| single(Value::Bool(f.abs() == f64::MAX)))
+            }
+            ("isnormal", []) => {
+                float(value).and_then(|f| single(Value::Bool(f.is_normal() && f.abs() != f64::MAX)))
+            }
+            ("type", []) => single(Value::String(type_str(value).to_string())),
+            ("any", []) => any(value, &Query::Iterator, &Query::Identity, scope),
+            ("any", [cond]) => any(value, &Query::Iterator, cond, scope),
+            ("any", [gen, cond]) => any(value, gen, cond, scope),
+            ("all", []) => all(value, &Query::Iterator, &Query::Identity, scope),
+            ("all", [cond]) => all(value, &Query::Iterator, cond, scope),
+            ("all", [gen, cond]) => all(value, gen, cond, scope),
+            (name, args) => Err(QueryError::UnknownFunction(name.to_string(), args.len())),
+        }
+    }
+}
+
+enum Step {
+    Emit(Value),
+    Visit(Value),
+}
+
+/// Runs a recursive generator with an explicit stack, so that long iterations can't overflow
+/// the call stack. Visiting a value produces the steps that replace it, in output order, and
+/// values are only visited as results are taken, so an endless generator can still be limited.
+fn unfold<'a, F>(v: Value, visit: F) -> QueryIter<'a>
+where
+    F: Fn(&Value) -> Result<Vec<Step>, QueryError> + 'a,
+{
+    let mut stack = vec![Step::Visit(v)];
+    Box::new(iter::from_fn(move || loop {
+        match stack.pop()? {
+            Step::Emit(vv) => return Some(Ok(vv)),
+            Step::Visit(vv) => match visit(&vv) {
+                Ok(steps) => stack.extend(steps.into_iter().rev()),
+                Err(e) => {
+                    // Nothing follows an error
+                    stack.clear();
+                    return Some(Err(e));
+                }
+            },
+        }
+    }))
+}
+
+/// Emits every path below the input, each as many times as `select` gives for its value.
+fn all_paths<F>(v: &Value, scope: &Scope, select: F) -> QueryResult
+where
+    F: Fn(&Value) -> Result<usize, QueryError>,
+{
+    let mut res = Vec::new();
+    for (path, vv) in paths(&Query::Recurse, v, scope)?.into_iter().skip(1) {
+        let n = select(&vv)?;
+        res.extend(std::iter::repeat(Value::Array(path)).take(n));
+    }
+    Ok(res)
+}
+
+/// Converts a value into `[path, leaf]` events, each container being closed by a `[path]` event
+/// for its last child once everything within it has been emitted.
+fn tostream(v: &Value, scope: &Scope) -> QueryResult {
+    let mut res = Vec::new();
+    let mut closing: Vec<(Path, Value)> = Vec::new();
+    for (path, vv) in paths(&Query::Recurse, v, scope)? {
+        while let Some((open, event)) = closing.pop() {
+            if path.starts_with(&open) {
+                closing.push((open, event));
+                break;
+            }
+            res.push(event);
+        }
+
+        let last = match &vv {
+            Value::Array(arr) if !arr.is_empty() => Some(Value::from(arr.len() - 1)),
+            Value::Object(map) => map.keys().max().map(|k| Value::String(k.clone())),
+            _ => None,
+        };
+        match last {
+            Some(k) => {
+                let mut end = path.clone();
+                end.push(k);
+                closing.push((path, json!([end])));
+            }
+            None => res.push(json!([path, vv])),
+        }
+    }
+    res.extend(closing.into_iter().rev().map(|(_, event)| event));
+    Ok(res)
+}
+
+/// Reassembles the values described by a stream of events, emitting each one as soon as its
+/// top-level closing event (or lone top-level leaf) is reached.
+fn fromstream(events: &[Value]) -> QueryResult {
+    let mut res = Vec::new();
+    let mut current = Value::Null;
+    for event in events {
+        let (path, leaf) = match event.as_array().map(Vec::as_slice) {
+            Some([Value::Array(path), leaf]) => (path, Some(leaf)),
+            Some([Value::Array(path)]) => (path, None),
+            _ => {
+                return Err(QueryError::Custom(format!(
+                    "Invalid stream event: {}",
+                    event
+                )))
+            }
+        };
+        let done = match leaf {
+            Some(leaf) => {
+                current = setpath(&current, path, leaf.clone())?;
+                path.is_empty()
+            }
+            None => path.len() == 1,
+        };
+        if done {
+            res.push(std::mem::take(&mut current));
+        }
+    }
+    Ok(res)
+}
+
+fn path_arg(v: &Value) -> Result<&[Value], QueryError> {
+    match v {
+        Value::Array(p) => Ok(p),
+        vv => Err(QueryError::Unsupported(type_str(vv), "used as a path")),
+    }
+}
+
+fn limit<'a>(v: Value, n: &Value, f: &'a Query, scope: Scope) -> QueryIter<'a> {
+    match index(n, &v) {
+        Ok(n) if n >= 0 => Box::new(f.execute_iter_in(v, scope).take(n as usize)),
+        Ok(_) => Box::new(iter::once(Err(QueryError::Custom(
+            "Invalid limit: count cannot be negative".to_string(),
+        )))),
+        Err(e) => Box::new(iter::once(Err(e))),
+    }
+}
+
+fn nth(v: &Value, n: i64, f: &Query, scope: &Scope) -> QueryResult {
+    if n < 0 {
+        return Err(QueryError::Custom(
+            "Out of bounds negative array index".to_string(),
+        ));
+    }
+    f.execute_iter_in(v.clone(), scope.clone())
+        .nth(n as usize)
+        .into_iter()
+        .collect()
+}
+
+/// Counts from a start up to, but not including, an end, in steps of one unless given. Every
+/// combination of the bounds' results is counted out, with the start varying slowest.
+fn range(v: &Value, args: &[Query], scope: &Scope) -> QueryIter<'static> {
+    let bounds = match args
+        .iter()
+        .map(|a| a.execute_in(v, scope))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(b) => b,
+        Err(e) => return Box::new(iter::once(Err(e))),
+    };
+    let (starts, ends, steps) = match bounds.as_slice() {
+        [end] => (vec![Value::from(0)], end.clone(), vec![Value::from(1)]),
+        [start, end] => (start.clone(), end.clone(), vec![Value::from(1)]),
+        [start, end, step] => (start.clone(), end.clone(), step.clone()),
+        _ => unreachable!(),
+    };
+    Box::new(iproduct!(starts, ends, steps).flat_map(|(start, end, step)| count(start, end, step)))
+}
+
+// As in jq, a step of zero counts nothing rather than repeating the start forever
+fn count(start: Value, end: Value, step: Value) -> QueryIter<'static> {
+    let (from, upto, by) = match (&start, &end, &step) {
+        (Value::Number(f), Value::Number(u), Value::Number(b)) => (f, u, b),
+        (Value::Number(_), Value::Number(_), vv) | (Value::Number(_), vv, _) | (vv, _, _) => {
+            let e = QueryError::Unsupported(type_str(vv), "used as a range bound");
+            return Box::new(iter::once(Err(e)));
+        }
+    };
+    let upto = upto.as_f64().unwrap_or_default();
+    let within = move |n: f64, by: f64| (by > 0.0 && n < upto) || (by < 0.0 && n > upto);
+    // Integers are counted as integers, until they would overflow
+    match (from.as_i64(), by.as_i64()) {
+        (Some(from), Some(by)) => Box::new(
+            iter::successors(Some(from), move |n| n.checked_add(by))
+                .take_while(move |n| within(*n as f64, by as f64))
+                .map(|n| Ok(Value::from(n))),
+        ),
+        _ => {
+            let from = from.as_f64().unwrap_or_default();
+            let by = by.as_f64().unwrap_or_default();
+            Box::new(
+                iter::successors(Some(from), move |n| Some(n + by))
+                    .take_while(move |n| within(*n, by))
+                    .map(|n| Ok(Value::from(n))),
+            )
+        }
+    }
+}
+
+// Fractional indices are truncated towards negative infinity, as in jq. Errors describe the
+// value that was being indexed.
+fn index(v: &Value, within: &Value) -> Result<i64, QueryError> {
+    match v {
+        Value::Number(n) => n
+            .as_i64()
+            .or_else(|| n.as_f64().map(|f| f.floor() as i64))
+            .ok_or(QueryError::Numerical),
+        vv => Err(QueryError::Index(describe(within), type_str(vv))),
+    }
+}
+
+fn keys(v: &Value) -> QueryResult {
+    let keys = match v {
+        Value::Object(map) => sorted_entries(map)
+            .into_iter()
+            .map(|(k, _)| Value::String(k.clone()))
+            .collect(),
+        Value::Array(arr) => (0..arr.len()).map(Value::from).collect(),
+        vv => return Err(QueryError::Unsupported(type_str(vv), "used with keys")),
+    };
+    single(Value::Array(keys))
+}
+
+fn has_key(container: &Value, key: &Value) -> Result<bool, QueryError> {
+    match (container, key) {
+        (Value::Object(map), Value::String(k)) => Ok(map.contains_key(k)),
+        (Value::Array(arr), Value::Number(_)) => {
+            let i = index(key, container)?;
+            Ok(i >= 0 && (i as usize) < arr.len())
+        }
+        (c, k) => Err(QueryError::Index(describe(c), type_str(k))),
+    }
+}
+
+fn length(v: &Value) -> QueryResult {
+    let len = match v {
+        Value::Null => 0,
+        Value::Bool(_) => return Err(QueryError::Length(type_str(v), v.to_string())),
+        // The length of a number is its absolute value
+        Value::Number(n) => {
+            return single(match (n.as_i64(), n.as_f64()) {
+                (Some(i), _) if i != i64::MIN => Value::from(i.abs()),
+                (_, Some(f)) => Value::from(f.abs()),
+                _ => Value::Number(n.clone()),
+            })
+        }
+        Value::String(s) => s.chars().count(),
+        Value::Array(arr) => arr.len(),
+        Value::Object(map) => map.len(),
+    };
+    single(Value::from(len))
+}
+
+// Only ASCII letters change case, everything else is left as it is
+fn ascii_case(v: &Value, f: fn(&str) -> String, action: &'static str) -> QueryResult {
+    match v {
+        Value::String(s) => single(Value::String(f(s))),
+        vv => Err(QueryError::Unsupported(type_str(vv), action)),
+    }
+}
+
+// Only a single occurrence is removed, and anything that isn't a string with the affix is
+// passed through unchanged
+fn trimstr<F>(v: &Value, affix: &Query, scope: &Scope, strip: F) -> QueryResult
+where
+    F: for<'a> Fn(&'a str, &str) -> Option<&'a str>,
+{
+    Ok(affix
+        .execute_in(v, scope)?
+        .iter()
+        .map(|a| match (v, a) {
+            (Value::String(s), Value::String(a)) => {
+                Value::String(strip(s, a).unwrap_or(s).to_string())
+            }
+            _ => v.clone(),
+        })
+        .collect())
+}
+
+fn reverse(v: &Value) -> QueryResult {
+    match v {
+        Value::Array(arr) => single(Value::Array(arr.iter().rev().cloned().collect())),
+        Value::String(s) => single(Value::String(s.chars().rev().collect())),
+        Value::Null => single(Value::Array(Vec::new())),
+        vv => Err(QueryError::Unsupported(type_str(vv), "reversed")),
+    }
+}
+
+// Conditions are only evaluated until the result is decided
+fn any(v: &Value, gen: &Query, cond: &Query, scope: &Scope) -> QueryResult {
+    for vv in gen.execute_iter_in(v.clone(), scope.clone()) {
+        if cond.execute_in(&vv?, scope)?.iter().any(truthy) {
+            return single(Value::Bool(true));
+        }
+    }
+    single(Value::Bool(false))
+}
+
+fn all(v: &Value, gen: &Query, cond: &Query, scope: &Scope) -> QueryResult {
+    for vv in gen.execute_iter_in(v.clone(), scope.clone()) {
+        if !cond.execute_in(&vv?, scope)?.iter().all(truthy) {
+            return single(Value::Bool(false));
+        }
+    }
+    single(Value::Bool(true))
+}
+
+/// Runs `f` on the positions of every result of `x` within the input, where anything without
+/// positions, such as an empty needle, produces `null`.
+fn positions<F>(v: &Value, x: &Query, scope: &Scope, f: F) -> QueryResult
+where
+    F: Fn(Vec<usize>) -> Value,
+{
+    x.execute_in(v, scope)?
+        .iter()
+        .map(|needle| Ok(find_all(v, needle)?.map_or(Value::Null, &f)))
+        .collect()
+}
+
+// Occurrences may overlap, and strings are searched by codepoint rather than by byte
+fn find_all(v: &Value, needle: &Value) -> Result<Option<Vec<usize>>, QueryError> {
+    let found = match (v, needle) {
+        (Value::Null, _) => return Ok(None),
+        (Value::String(s), Value::String(n)) => {
+            if n.is_empty() {
+                return Ok(None);
+            }
+            let chars: Vec<char> = s.chars().collect();
+            let needle: Vec<char> = n.chars().collect();
+            find_subsequence(&chars, &needle, |a, b| a == b)
+        }
+        (Value::Array(arr), Value::Array(n)) => {
+            if n.is_empty() {
+                return Ok(None);
+            }
+            find_subsequence(arr, n, |a, b| cmp_values(a, b) == Ordering::Equal)
+        }
+        (Value::Array(arr), n) => find_subsequence(arr, std::slice::from_ref(n), |a, b| {
+            cmp_values(a, b) == Ordering::Equal
+        }),
+        (vv, n) => return Err(QueryError::Index(describe(vv), type_str(n))),
+    };
+    Ok(Some(found))
+}
+
+fn find_subsequence<T, F>(haystack: &[T], needle: &[T], eq: F) -> Vec<usize>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(|(_, w)| w.iter().zip(needle).all(|(a, b)| eq(a, b)))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+fn split(v: &Value, sep: &Value) -> QueryResult {
+    let (s, sep) = match (v, sep) {
+        (Value::String(s), Value::String(sep)) => (s, sep),
+        (Value::String(_), sep) => {
+            return Err(QueryError::Unsupported(type_str(sep), "used to split"))
+        }
+        (vv, _) => return Err(QueryError::Unsupported(type_str(vv), "split")),
+    };
+    let pieces: Vec<Value> = if s.is_empty() {
+        Vec::new()
+    } else if sep.is_empty() {
+        s.chars().map(|c| Value::String(c.to_string())).collect()
+    } else {
+        s.split(sep.as_str())
+            .map(|p| Value::String(p.to_string()))
+            .collect()
+    };
+    single(Value::Array(pieces))
+}
+
+fn test(s: &str, p: &Pattern) -> QueryResult {
+    single(Value::Bool(p.matches(s).next().is_some()))
+}
+
+fn capture(s: &str, p: &Pattern) -> QueryResult {
+    Ok(p.captures(s).map(|c| p.named_groups(&c)).collect())
+}
+
+/// Runs `f` against the input string for every combination of regex and flags, where no flags
+/// query is the same as `null` flags.
+fn with_pattern<F>(v: &Value, re: &Query, flags: Option<&Query>, scope: &Scope, f: F) -> QueryResult
+where
+    F: Fn(&str, &Pattern) -> QueryResult,
+{
+    let s = match v {
+        Value::String(s) => s,
+        vv => return Err(QueryError::Unsupported(type_str(vv), "matched")),
+    };
+    let mut res = Vec::new();
+    let flags = match flags {
+        Some(f) => f.execute_in(v, scope)?,
+        None => vec![Value::Null],
+    };
+    for flags in flags {
+        for re in re.execute_in(v, scope)? {
+            res.extend(f(s, &Pattern::new(&re, &flags)?)?);
+        }
+    }
+    Ok(res)
+}
+
+fn sort(v: &Value) -> QueryResult {
+    match v {
+        Value::Array(arr) => {
+            let mut sorted = arr.clone();
+            // Stable so that equal values, such as `1` and `1.0`, keep their input order
+            sorted.sort_by(cmp_values);
+            single(Value::Array(sorted))
+        }
+        vv => Err(QueryError::Unsupported(type_str(vv), "sorted")),
+    }
+}
+
+// Integers stay integers, with the most negative one becoming an unsigned integer
+fn abs(v: &Value) -> QueryResult {
+    let n = match v {
+        Value::Number(n) => n,
+        vv => return Err(QueryError::Unsupported(type_str(vv), "made absolute")),
+    };
+    single(match n.as_i64() {
+        Some(i) => Value::from(i.unsigned_abs()),
+        None if n.is_u64() => v.clone(),
+        None => Value::from(n.as_f64().unwrap_or_default().abs()),
+    })
+}
+
+/// Reads a number as a float. JSON numbers are always finite, so there's no way to represent
+/// `nan` and infinities faithfully: `nan` is `null`, which sorts below every number as NaN does
+/// in jq but isn't a number here, and `infinite` is `f64::MAX`, which can't be told apart from
+/// that value written in the input. For that reason there's no `isnan`.
+fn float(v: &Value) -> Result<f64, QueryError> {
+    match v {
+        Value::Number(n) => Ok(n.as_f64().unwrap_or_default()),
+        vv => Err(QueryError::Unsupported(type_str(vv), "used as a number")),
+    }
+}
+
+fn pass_if(v: &Value, keep: bool) -> QueryResult {
+    if keep {
+        single(v.clone())
+    } else {
+        empty()
+    }
+}
+
+/// Pairs each element of an array with all of the keys `f` produces for it, which are compared
+/// as arrays so that later keys break ties between earlier ones.
+fn keyed<'a>(
+    v: &'a Value,
+    f: &Query,
+    scope: &Scope,
+    action: &'static str,
+) -> Result<Vec<(Vec<Value>, &'a Value)>, QueryError> {
+    match v {
+        Value::Array(arr) => arr
+            .iter()
+            .map(|vv| Ok((f.execute_in(vv, scope)?, vv)))
+            .collect(),
+        vv => Err(QueryError::Unsupported(type_str(vv), action)),
+    }
+}
+
+fn sort_by(v: &Value, f: &Query, scope: &Scope) -> QueryResult {
+    let mut keyed = keyed(v, f, scope, "sorted")?;
+    // Stable so that elements with equal keys keep their input order
+    keyed.sort_by(|(k, _), (l, _)| cmp_arrays(k, l));
+    single(Value::Array(
+        keyed.into_iter().map(|(_, vv)| vv.clone()).collect(),
+    ))
+}
+
+// As in jq the first of several minimal elements wins, but the last of several maximal ones
+fn min_by(v: &Value, f: &Query, scope: &Scope) -> QueryResult {
+    let keyed = keyed(v, f, scope, "compared")?;
+    let min = keyed.into_iter().min_by(|(k, _), (l, _)| cmp_arrays(k, l));
+    single(min.map_or(Value::Null, |(_, vv)| vv.clone()))
+}
+
+fn max_by(v: &Value, f: &Query, scope: &Scope) -> QueryResult {
+    let keyed = keyed(v, f, scope, "compared")?;
+    let max = keyed.into_iter().max_by(|(k, _), (l, _)| cmp_arrays(k, l));
+    single(max.map_or(Value::Null, |(_, vv)| vv.clone()))
+}
+
+fn group_by(v: &Value, f: &Query, scope: &Scope) -> QueryResult {
+    let mut keyed = keyed(v, f, scope, "grouped")?;
+    // Stable so that elements with equal keys keep their input order within a group
+    keyed.sort_by(|(k, _), (l, _)| cmp_arrays(k, l));
+
+    let mut groups: Vec<(Vec<Value>, Vec<Value>)> = Vec::new();
+    for (k, vv) in keyed {
+        match groups.last_mut() {
+            Some((l, group)) if cmp_arrays(&k, l) == Ordering::Equal => group.push(vv.clone()),
+            _ => groups.push((k, vec![vv.clone()])),
+        }
+    }
+    single(Value::Array(
+        groups
+            .into_iter()
+            .map(|(_, group)| Value::Array(group))
+            .collect(),
+    ))
+}
+
+fn error(v: &Value) -> QueryError {
+    match v {
+        Value::String(s) => QueryError::Custom(s.clone()),
+        vv => QueryError::Custom(format!("{} (not a string)", vv)),
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.args.is_empty() {
+            write!(f, "({})", self.args.iter().join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Parseable for Function {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        map(
+            pair(
+                parse_identifier,
+                opt(delimited(
+                    char('('),
+                    separated_list1(char(';'), space::around(parse_pipe)),
+                    char(')'),
+                )),
+            ),
+            |(name, args)| Function::new(name, args.unwrap_or_default()),
+        )(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raw::Raw;
+
+    use super::*;
+
+    #[test]
+    fn parse_function() {
+        assert!(Function::parse("1foo").is_err());
+        assert!(Function::parse("foo()").is_err());
+        assert!(Function::parse("foo(.;)").is_err());
+        assert!(Function::parse("foo (.)").is_err());
+
+        assert_eq!(
+            Function::new("empty", vec![]),
+            Function::parse("empty").unwrap()
+        );
+        assert_eq!(
+            Function::new("error", vec![Query::Raw(Raw::parse("\"boom\"").unwrap())]),
+            Function::parse("error(\"boom\")").unwrap()
+        );
+        assert_eq!(
+            Function::new("f_1", vec![Query::Identity, Query::Iterator]),
+            Function::parse("f_1( . ; .[] )").unwrap()
+        );
+    }
+}
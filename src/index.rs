@@ -1,15 +1,19 @@
+use std::fmt;
+
 use crate::{
-    null,
-    parse::{ParseError, Parseable},
-    query::Executable,
+    describe, null,
+    parse::{is_identifier, ParseError, Parseable},
+    query::{iterate_results, Executable},
     range::Range,
-    single, space, type_str, QueryError, QueryResult,
+    scope::Scope,
+    single, space, string, QueryError, QueryResult,
 };
+use itertools::Itertools;
 use nom::{
     branch::alt,
-    bytes::complete::take_while1,
-    character::complete::{char, i32},
+    character::complete::{char, i64},
     combinator::map,
+    multi::separated_list1,
     sequence::delimited,
     IResult,
 };
@@ -18,17 +22,22 @@ use serde_json::{Map, Value};
 #[derive(Debug, PartialEq, Clone)]
 pub enum Index {
     String(String),
-    Integer(i32),
+    Integer(i64),
     Slice(Range),
+    /// Several keys in one index, e.g. `.["a","b"]` or `.[4,2]`, producing a result for each in
+    /// turn.
+    Keys(Vec<Index>),
 }
 
 impl Executable for Index {
-    fn execute(&self, v: &Value) -> QueryResult {
+    fn execute_in(&self, v: &Value, scope: &Scope) -> QueryResult {
         match (v, self) {
+            (v, Index::Keys(keys)) => iterate_results(keys.iter().map(|k| k.execute_in(v, scope))),
             (Value::String(s), Index::Slice(r)) => {
-                let range = r.normalize(s.len());
-                let sliced = s[range].to_string();
-                single(Value::String(sliced))
+                // Slice by codepoint rather than byte so multibyte characters stay intact
+                let chars: Vec<char> = s.chars().collect();
+                let range = r.normalize(chars.len());
+                single(Value::String(chars[range].iter().collect()))
             }
             (Value::Array(vec), Index::Slice(r)) => {
                 let range = r.normalize(vec.len());
@@ -36,9 +45,12 @@ impl Executable for Index {
             }
             (Value::Object(map), Index::String(s)) => index_object(map, s),
             (Value::Array(arr), Index::Integer(i)) => index_array(arr, *i),
-            (v, Index::String(_)) => Err(QueryError::Index(type_str(v), "string")),
-            (v, Index::Integer(_)) => Err(QueryError::Index(type_str(v), "number")),
-            (v, Index::Slice(_)) => Err(QueryError::Index(type_str(v), "slice")),
+            (_, Index::String(_)) | (_, Index::Integer(_)) if scope.options().lenient_index => {
+                null()
+            }
+            (v, Index::String(_)) => Err(QueryError::Index(describe(v), "string")),
+            (v, Index::Integer(_)) => Err(QueryError::Index(describe(v), "number")),
+            (v, Index::Slice(_)) => Err(QueryError::Index(describe(v), "slice")),
         }
     }
 }
@@ -51,10 +63,10 @@ fn index_object(map: &Map<String, Value>, s: &str) -> QueryResult {
     }
 }
 
-fn index_array(arr: &[Value], i: i32) -> QueryResult {
+fn index_array(arr: &[Value], i: i64) -> QueryResult {
     let index = if i < 0 {
-        let j = -i as usize;
-        if j >= arr.len() {
+        let j = i.unsigned_abs() as usize;
+        if j > arr.len() {
             return null();
         }
         arr.len() - j
@@ -69,16 +81,51 @@ fn index_array(arr: &[Value], i: i32) -> QueryResult {
     }
 }
 
+impl Index {
+    /// Writes the index as it appears when chained onto a preceding query, e.g. the `[0]` of
+    /// `.foo[0]`.
+    pub(crate) fn fmt_chained(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Index::String(s) if is_identifier(s) => write!(f, ".{}", s),
+            _ => write!(f, "[{}]", self.fmt_key()),
+        }
+    }
+
+    /// Writes the index as it appears within brackets.
+    fn fmt_key(&self) -> String {
+        match self {
+            Index::String(s) => Value::from(s.as_str()).to_string(),
+            Index::Integer(i) => i.to_string(),
+            Index::Slice(r) => r.to_string(),
+            Index::Keys(keys) => keys.iter().map(Index::fmt_key).join(", "),
+        }
+    }
+}
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Index::String(s) if is_identifier(s) => write!(f, ".{}", s),
+            _ => {
+                write!(f, ".")?;
+                self.fmt_chained(f)
+            }
+        }
+    }
+}
+
 impl Parseable for Index {
     fn parser(input: &str) -> IResult<&str, Index, ParseError> {
         delimited(
             char('['),
             space::around(alt((
                 map(Range::parser, Index::Slice),
-                map(i32, Index::Integer),
                 map(
-                    delimited(char('"'), take_while1(|c| c != '"'), char('"')),
-                    |s: &str| Index::String(s.to_string()),
+                    separated_list1(space::around(char(',')), parse_key),
+                    |mut keys| match keys.len() {
+                        1 => keys.remove(0),
+                        _ => Index::Keys(keys),
+                    },
                 ),
             ))),
             char(']'),
@@ -86,6 +133,13 @@ impl Parseable for Index {
     }
 }
 
+fn parse_key(input: &str) -> IResult<&str, Index, ParseError> {
+    alt((
+        map(i64, Index::Integer),
+        map(string::json_string, Index::String),
+    ))(input)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::query::Query;
@@ -107,6 +161,20 @@ mod tests {
             Index::parse("[ \"f o o\" ]").unwrap()
         );
 
+        assert_eq!(
+            Index::Keys(vec![
+                Index::String("a".to_string()),
+                Index::String("b c".to_string())
+            ]),
+            Index::parse("[\"a\", \"b c\"]").unwrap()
+        );
+        assert_eq!(
+            Index::String("a\"b".to_string()),
+            Index::parse(r#"["a\"b"]"#).unwrap()
+        );
+        assert!(Index::parse("[\"a\",]").is_err());
+        assert!(Index::parse("[,\"a\"]").is_err());
+
         // Shorthand object index only through full query
         // This is because of ambiguity with initial dot
         assert_eq!(
@@ -123,6 +191,21 @@ mod tests {
         assert_eq!(Index::Integer(0), Index::parse("[ 0 ]").unwrap());
         assert_eq!(Index::Integer(-1), Index::parse("[-1]").unwrap());
         assert_eq!(Index::Integer(9001), Index::parse("[9001]").unwrap());
+        assert_eq!(
+            Index::Integer(3000000000),
+            Index::parse("[3000000000]").unwrap()
+        );
+        assert_eq!(
+            Index::Integer(i64::MIN),
+            Index::parse("[-9223372036854775808]").unwrap()
+        );
+        assert!(Index::parse("[9223372036854775808]").is_err());
+
+        assert_eq!(
+            Index::Keys(vec![Index::Integer(4), Index::Integer(-2)]),
+            Index::parse("[4, -2]").unwrap()
+        );
+        assert!(Index::parse("[4,]").is_err());
     }
 
     #[test]
@@ -139,9 +222,17 @@ mod tests {
             Index::parse("[ -1:2 ]").unwrap()
         );
         assert_eq!(Index::Slice(Range::upper(2)), Index::parse("[:2]").unwrap());
+        assert!(matches!(
+            Query::parse(".a[::2]"),
+            Err(ParseError::StepSlice(s)) if s == "::2"
+        ));
         assert_eq!(
             Index::Slice(Range::new((9001, -9001))),
             Index::parse("[9001:-9001]").unwrap()
         );
+        assert_eq!(
+            Index::Slice(Range::new((-3000000000, 3000000000))),
+            Index::parse("[-3000000000:3000000000]").unwrap()
+        );
     }
 }
@@ -0,0 +1,361 @@
+use std::{
+    fmt,
+    io::{self, BufReader, Bytes, Read},
+    str::FromStr,
+};
+
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum InputError {
+    #[error("Failed to read input: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse document at byte {0}: {1}")]
+    Document(usize, serde_json::Error),
+    #[error("{0}: {1}")]
+    File(String, Box<InputError>),
+}
+
+/// The record separator that starts each JSON text in an RFC 7464 sequence.
+pub const RS: u8 = 0x1e;
+
+/// How to handle an input object that contains the same key more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DuplicateKeys {
+    /// Reject the document as malformed.
+    Error,
+    /// Keep the value of the first occurrence.
+    First,
+    /// Keep the value of the last occurrence, as `serde_json` does.
+    #[default]
+    Last,
+}
+
+impl FromStr for DuplicateKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(DuplicateKeys::Error),
+            "first" => Ok(DuplicateKeys::First),
+            "last" => Ok(DuplicateKeys::Last),
+            _ => Err(format!(
+                "Invalid duplicate key mode {}, expected error, first or last",
+                s
+            )),
+        }
+    }
+}
+
+/// Lazily splits a reader into whitespace-separated JSON documents.
+///
+/// Document boundaries are found by scanning for balanced brackets before handing each
+/// document to `serde_json`, so a malformed document is reported with its byte offset and
+/// the documents following it can still be read.
+pub struct Inputs<R: Read> {
+    bytes: Bytes<BufReader<R>>,
+    pending: Option<u8>,
+    offset: usize,
+    seq: bool,
+    duplicate_keys: DuplicateKeys,
+}
+
+impl<R: Read> Inputs<R> {
+    pub fn new(reader: R) -> Self {
+        Inputs {
+            bytes: BufReader::new(reader).bytes(),
+            pending: None,
+            offset: 0,
+            seq: false,
+            duplicate_keys: DuplicateKeys::default(),
+        }
+    }
+
+    /// Reads the input as an RFC 7464 JSON text sequence, where a record separator ends any
+    /// document that is still open. Truncated documents are reported as malformed.
+    pub fn with_seq(mut self, seq: bool) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    pub fn with_duplicate_keys(mut self, duplicate_keys: DuplicateKeys) -> Self {
+        self.duplicate_keys = duplicate_keys;
+        self
+    }
+
+    fn is_separator(&self, b: u8) -> bool {
+        is_whitespace(b) || (self.seq && b == RS)
+    }
+
+    fn next_byte(&mut self) -> Option<io::Result<u8>> {
+        let next = match self.pending.take() {
+            Some(b) => Some(Ok(b)),
+            None => self.bytes.next(),
+        };
+        if let Some(Ok(_)) = next {
+            self.offset += 1;
+        }
+        next
+    }
+
+    fn unread(&mut self, b: u8) {
+        self.offset -= 1;
+        self.pending = Some(b);
+    }
+
+    fn read_document(&mut self, first: u8) -> io::Result<Vec<u8>> {
+        let mut buf = vec![first];
+        let mut depth = 0usize;
+        let mut in_string = first == b'"';
+        let mut escaped = false;
+        match first {
+            b'{' | b'[' => depth += 1,
+            b'"' => {}
+            _ => {
+                // Scalar tokens run until whitespace or the start of another document
+                while let Some(b) = self.next_byte() {
+                    let b = b?;
+                    if self.is_separator(b) || matches!(b, b'{' | b'[' | b'"') {
+                        self.unread(b);
+                        break;
+                    }
+                    buf.push(b);
+                }
+                return Ok(buf);
+            }
+        }
+
+        while let Some(b) = self.next_byte() {
+            let b = b?;
+            if self.seq && b == RS {
+                self.unread(b);
+                break;
+            }
+            buf.push(b);
+            if in_string {
+                match (escaped, b) {
+                    (true, _) => escaped = false,
+                    (false, b'\\') => escaped = true,
+                    (false, b'"') => in_string = false,
+                    _ => {}
+                }
+                if !in_string && depth == 0 {
+                    break;
+                }
+                continue;
+            }
+            match b {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(buf)
+    }
+}
+
+impl<R: Read> Iterator for Inputs<R> {
+    type Item = Result<Value, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = loop {
+            match self.next_byte()? {
+                Ok(b) if self.is_separator(b) => continue,
+                Ok(b) => break b,
+                Err(e) => return Some(Err(e.into())),
+            }
+        };
+
+        let start = self.offset - 1;
+        let document = match self.read_document(first) {
+            Ok(d) => d,
+            Err(e) => return Some(Err(e.into())),
+        };
+        Some(
+            parse_document(&document, self.duplicate_keys)
+                .map_err(|e| InputError::Document(start, e)),
+        )
+    }
+}
+
+fn parse_document(document: &[u8], duplicate_keys: DuplicateKeys) -> serde_json::Result<Value> {
+    let mut de = serde_json::Deserializer::from_slice(document);
+    let value = Document(duplicate_keys).deserialize(&mut de)?;
+    de.end()?;
+    Ok(value)
+}
+
+/// Builds a `Value` like `serde_json` does, but with control over duplicate object keys.
+#[derive(Clone, Copy)]
+struct Document(DuplicateKeys);
+
+impl<'de> DeserializeSeed<'de> for Document {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Document {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::from(n))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Value, E> {
+        Ok(Value::from(n))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Value, E> {
+        Ok(Number::from_f64(n).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut arr = Vec::new();
+        while let Some(vv) = seq.next_element_seed(self)? {
+            arr.push(vv);
+        }
+        Ok(Value::Array(arr))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut obj = Map::new();
+        while let Some(k) = map.next_key::<String>()? {
+            let vv = map.next_value_seed(self)?;
+            match (self.0, obj.contains_key(&k)) {
+                (DuplicateKeys::Error, true) => {
+                    return Err(de::Error::custom(format!("duplicate key `{}`", k)))
+                }
+                (DuplicateKeys::First, true) => {}
+                _ => {
+                    obj.insert(k, vv);
+                }
+            }
+        }
+        Ok(Value::Object(obj))
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Vec<Result<Value, InputError>> {
+        Inputs::new(input.as_bytes()).collect()
+    }
+
+    #[test]
+    fn multiple_documents() {
+        let docs = read(" 1 \"a b\"{\"a\":[1,{\"b\":\"}\"}]}[2]null\n");
+        let docs: Vec<String> = docs.into_iter().map(|d| d.unwrap().to_string()).collect();
+        assert_eq!(
+            vec!["1", "\"a b\"", r#"{"a":[1,{"b":"}"}]}"#, "[2]", "null"],
+            docs
+        );
+
+        assert!(read("").is_empty());
+        assert!(read(" \n\t").is_empty());
+    }
+
+    #[test]
+    fn malformed_documents() {
+        let docs = read(r#"{"a":1} not-json {"b":2}"#);
+        assert_eq!(3, docs.len());
+        assert_eq!(r#"{"a":1}"#, docs[0].as_ref().unwrap().to_string());
+        assert!(matches!(docs[1], Err(InputError::Document(8, _))));
+        assert_eq!(r#"{"b":2}"#, docs[2].as_ref().unwrap().to_string());
+
+        let docs = read(r#"1 {"a":} 2"#);
+        assert_eq!(3, docs.len());
+        assert!(matches!(docs[1], Err(InputError::Document(2, _))));
+
+        let docs = read(r#"[1, 2"#);
+        assert_eq!(1, docs.len());
+        assert!(matches!(docs[0], Err(InputError::Document(0, _))));
+    }
+
+    #[test]
+    fn json_sequence() {
+        let read_seq = |input: &str| -> Vec<Result<Value, InputError>> {
+            Inputs::new(input.as_bytes()).with_seq(true).collect()
+        };
+
+        let docs = read_seq("\x1e{\"a\":1}\n\x1e[2]\n");
+        assert_eq!(2, docs.len());
+        assert_eq!(r#"{"a":1}"#, docs[0].as_ref().unwrap().to_string());
+        assert_eq!("[2]", docs[1].as_ref().unwrap().to_string());
+
+        let docs = read_seq("\x1e\x1e1\x1e2\x1e\n\x1e");
+        let docs: Vec<String> = docs.into_iter().map(|d| d.unwrap().to_string()).collect();
+        assert_eq!(vec!["1", "2"], docs);
+
+        let docs = read_seq("\x1e{\"a\":\n\x1e\"b\"\n");
+        assert_eq!(2, docs.len());
+        assert!(matches!(docs[0], Err(InputError::Document(1, _))));
+        assert_eq!(r#""b""#, docs[1].as_ref().unwrap().to_string());
+
+        // Without sequence parsing the separator is not valid JSON
+        assert!(read("\x1e1").iter().any(|d| d.is_err()));
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let read_with = |input: &str, duplicate_keys| -> Vec<Result<Value, InputError>> {
+            Inputs::new(input.as_bytes())
+                .with_duplicate_keys(duplicate_keys)
+                .collect()
+        };
+        let input = r#"{"a":1,"a":2} [{"b":{"c":1,"c":2}}] {"a":3}"#;
+
+        let docs = read_with(input, DuplicateKeys::Last);
+        let docs: Vec<String> = docs.into_iter().map(|d| d.unwrap().to_string()).collect();
+        assert_eq!(vec![r#"{"a":2}"#, r#"[{"b":{"c":2}}]"#, r#"{"a":3}"#], docs);
+
+        let docs = read_with(input, DuplicateKeys::First);
+        let docs: Vec<String> = docs.into_iter().map(|d| d.unwrap().to_string()).collect();
+        assert_eq!(vec![r#"{"a":1}"#, r#"[{"b":{"c":1}}]"#, r#"{"a":3}"#], docs);
+
+        let docs = read_with(input, DuplicateKeys::Error);
+        assert_eq!(3, docs.len());
+        assert!(matches!(docs[0], Err(InputError::Document(0, _))));
+        assert!(matches!(docs[1], Err(InputError::Document(14, _))));
+        assert_eq!(r#"{"a":3}"#, docs[2].as_ref().unwrap().to_string());
+
+        assert_eq!(Ok(DuplicateKeys::First), "first".parse());
+        assert!("other".parse::<DuplicateKeys>().is_err());
+    }
+}
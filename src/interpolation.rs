@@ -0,0 +1,130 @@
+use std::fmt;
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::char,
+    combinator::{map, verify},
+    multi::{fold_many1, many0},
+    sequence::delimited,
+    IResult,
+};
+use serde_json::Value;
+
+use crate::{
+    parse::{parse_pipe, ParseError, Parseable},
+    query::{Executable, Query},
+    scope::Scope,
+    space, string, QueryResult,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Part {
+    Literal(String),
+    Query(Query),
+}
+
+/// A string literal with `\(...)` queries embedded in it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Interpolation(pub Vec<Part>);
+
+impl Executable for Interpolation {
+    // Every combination of the embedded queries' results is produced, with the earliest
+    // query varying fastest as in jq
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        let mut strings = vec![String::new()];
+        for part in self.0.iter() {
+            strings = match part {
+                Part::Literal(s) => strings.into_iter().map(|prefix| prefix + s).collect(),
+                Part::Query(q) => {
+                    let results = q.execute_in(value, scope)?;
+                    results
+                        .iter()
+                        .flat_map(|vv| {
+                            let text = match vv {
+                                Value::String(s) => s.clone(),
+                                vv => vv.to_string(),
+                            };
+                            strings.iter().map(move |prefix| prefix.clone() + &text)
+                        })
+                        .collect()
+                }
+            };
+        }
+        Ok(strings.into_iter().map(Value::String).collect())
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"")?;
+        for part in self.0.iter() {
+            match part {
+                Part::Literal(s) => write!(f, "{}", string::escape(s))?,
+                Part::Query(q) => write!(f, "\\({})", q)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+impl Parseable for Interpolation {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        map(
+            verify(
+                delimited(char('"'), many0(parse_part), char('"')),
+                |parts: &Vec<Part>| parts.iter().any(|p| matches!(p, Part::Query(_))),
+            ),
+            Interpolation,
+        )(input)
+    }
+}
+
+fn parse_part(input: &str) -> IResult<&str, Part, ParseError> {
+    alt((
+        map(
+            delimited(tag("\\("), space::around(parse_pipe), char(')')),
+            Part::Query,
+        ),
+        map(
+            fold_many1(
+                alt((
+                    map(string::unescaped, str::to_string),
+                    map(string::escaped, String::from),
+                )),
+                String::new,
+                |s, piece| s + &piece,
+            ),
+            Part::Literal,
+        ),
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::Index;
+
+    use super::*;
+
+    #[test]
+    fn parse_interpolation() {
+        assert!(Interpolation::parse("\"foo\"").is_err());
+        assert!(Interpolation::parse("\"\"").is_err());
+        assert!(Interpolation::parse("\"\\(\"").is_err());
+        assert!(Interpolation::parse("\"\\(.a\"").is_err());
+        assert!(Interpolation::parse("\"\\(.a)\\c\"").is_err());
+
+        assert_eq!(
+            Interpolation(vec![
+                Part::Literal("a ".to_string()),
+                Part::Query(Query::Index(Index::String("b".to_string()))),
+                Part::Literal("\tc\"".to_string()),
+            ]),
+            Interpolation::parse("\"a \\( .b )\\tc\\\"\"").unwrap()
+        );
+        assert_eq!(
+            Interpolation(vec![Part::Query(Query::Identity)]),
+            Interpolation::parse("\"\\(.)\"").unwrap()
+        );
+    }
+}
@@ -1,36 +1,188 @@
-use serde_json::Value;
+use std::io::Read;
+
+use input::{InputError, Inputs};
+use query::Executable;
+use scope::Scope;
+use serde_json::{Map, Value};
 use thiserror::Error;
 
+mod assign;
 mod combinator;
+mod compare;
 mod construction;
+mod date;
+mod format;
+mod function;
 mod index;
+pub mod input;
+mod interpolation;
+mod matching;
 mod operators;
 pub mod parse;
+mod path;
 pub mod query;
 mod range;
 mod raw;
+mod reduce;
+pub mod scope;
 mod space;
+mod string;
+
+pub use parse::ParseError;
+pub use query::Query;
 
 pub type QueryResult = Result<Vec<Value>, QueryError>;
 
 #[derive(Error, Debug)]
 pub enum QueryError {
     #[error("Cannot index {0} with {1}")]
-    Index(&'static str, &'static str),
+    Index(String, &'static str),
     #[error("Cannot iterate over {0}")]
-    Iterate(&'static str),
+    Iterate(String),
     #[error("Cannot use {0} as object key")]
-    ObjectKey(&'static str),
+    ObjectKey(String),
+    #[error("{0} cannot be {1}")]
+    Unsupported(&'static str, &'static str),
     #[error("Numerical operation was not possible")]
     Numerical,
     #[error("Cannot {0} {1} and {2}")]
-    Operation(&'static str, &'static str, &'static str),
+    Operation(&'static str, String, String),
+    #[error("${0} is not defined")]
+    UnknownVariable(String),
+    #[error("Object construction would produce {0} objects, exceeding the limit of {1}")]
+    TooManyCombinations(usize, usize),
+    #[error("Invalid path expression with result {0}")]
+    InvalidPath(String),
+    #[error("{0}/{1} is not defined")]
+    UnknownFunction(String, usize),
+    #[error("{0} ({1}) has no length")]
+    Length(&'static str, String),
+    #[error("{0} is not a valid regex: {1}")]
+    Regex(String, String),
+    #[error("{0} is not a valid modifier string")]
+    RegexFlags(String),
+    #[error("{0:?} does not match the date format {1:?}")]
+    Date(String, String),
+    #[error("{0:?} is not a valid date format")]
+    DateFormat(String),
+    #[error(transparent)]
+    Input(#[from] InputError),
+    #[error("No more inputs")]
+    NoMoreInputs,
+    #[error("{0}")]
+    Custom(String),
+}
+
+/// Errors from parsing a query, reading input documents or executing a query against them.
+#[derive(Error, Debug)]
+pub enum RqError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Input(#[from] InputError),
+    #[error(transparent)]
+    Query(#[from] QueryError),
+}
+
+/// Lazily reads whitespace-separated JSON documents from `reader` and applies `query` to each
+/// of them in turn, yielding the results of every document in order.
+///
+/// A malformed document is yielded as an error and reading continues with the next one.
+pub fn query_stream<'a, R: Read + 'a>(
+    query: &'a Query,
+    reader: R,
+) -> impl Iterator<Item = Result<Value, RqError>> + 'a {
+    let scope = Scope::new();
+    Inputs::new(reader).flat_map(move |document| -> Box<dyn Iterator<Item = _>> {
+        match document {
+            Ok(value) => Box::new(
+                query
+                    .execute_iter_in(value, scope.clone())
+                    .map(|r| r.map_err(RqError::from)),
+            ),
+            Err(e) => Box::new(std::iter::once(Err(e.into()))),
+        }
+    })
+}
+
+/// Parses `query` and executes it against `input` in one call, returning every result.
+///
+/// ```
+/// let input = serde_json::json!({"a": [1, 2, 3]});
+/// let results = rq::run(".a[]", &input).unwrap();
+/// assert_eq!(vec![1, 2, 3], results);
+///
+/// assert!(matches!(rq::run(".a[", &input), Err(rq::RqError::Parse(_))));
+/// assert!(matches!(rq::run(".a.b", &input), Err(rq::RqError::Query(_))));
+/// ```
+pub fn run(query: &str, input: &Value) -> Result<Vec<Value>, RqError> {
+    let query: Query = query.parse()?;
+    Ok(query.execute(input)?)
+}
+
+/// Groups consecutive documents that share a key, computed by running `key` against each of
+/// them, and yields each group as an array.
+///
+/// Only the current group is held, so a stream already ordered by the key can be grouped
+/// without reading the whole of it. Equal keys that aren't adjacent produce separate groups.
+pub fn group_adjacent<I>(
+    documents: I,
+    key: Query,
+    scope: Scope,
+) -> impl Iterator<Item = Result<Value, QueryError>>
+where
+    I: Iterator<Item = Result<Value, InputError>>,
+{
+    AdjacentGroups {
+        documents,
+        key,
+        scope,
+        current: None,
+    }
+}
+
+struct AdjacentGroups<I> {
+    documents: I,
+    key: Query,
+    scope: Scope,
+    current: Option<(Vec<Value>, Vec<Value>)>,
+}
+
+impl<I: Iterator<Item = Result<Value, InputError>>> Iterator for AdjacentGroups<I> {
+    type Item = Result<Value, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let document = match self.documents.next() {
+                Some(Ok(d)) => d,
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => {
+                    return self
+                        .current
+                        .take()
+                        .map(|(_, group)| Ok(Value::Array(group)))
+                }
+            };
+            let key = match self.key.execute_in(&document, &self.scope) {
+                Ok(k) => k,
+                Err(e) => return Some(Err(e)),
+            };
+            match &mut self.current {
+                Some((k, group)) if *k == key => group.push(document),
+                current => {
+                    if let Some((_, group)) = current.replace((key, vec![document])) {
+                        return Some(Ok(Value::Array(group)));
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub(crate) fn type_str(v: &Value) -> &'static str {
     match v {
         Value::Null => "null",
-        Value::Bool(_) => "bool",
+        Value::Bool(_) => "boolean",
         Value::Number(_) => "number",
         Value::String(_) => "string",
         Value::Array(_) => "array",
@@ -38,6 +190,39 @@ pub(crate) fn type_str(v: &Value) -> &'static str {
     }
 }
 
+/// Describes a value for an error message by its type and a compact rendering of the value,
+/// truncated so that large values don't swamp the message.
+pub(crate) fn describe(v: &Value) -> String {
+    truncated(type_str(v), v.to_string())
+}
+
+/// Describes part of an array as `describe` would, without copying it into an array of its own.
+pub(crate) fn describe_slice(arr: &[Value]) -> String {
+    truncated("array", serde_json::to_string(arr).unwrap_or_default())
+}
+
+fn truncated(kind: &str, json: String) -> String {
+    const MAX_LEN: usize = 80;
+    if json.chars().count() > MAX_LEN {
+        let truncated: String = json.chars().take(MAX_LEN - 3).collect();
+        format!("{} ({}...)", kind, truncated)
+    } else {
+        format!("{} ({})", kind, json)
+    }
+}
+
+/// The entries of an object in the sorted key order jq uses, whether or not `serde_json` is
+/// preserving insertion order.
+pub(crate) fn sorted_entries(map: &Map<String, Value>) -> Vec<(&String, &Value)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    entries
+}
+
+pub(crate) fn truthy(v: &Value) -> bool {
+    !matches!(v, Value::Null | Value::Bool(false))
+}
+
 pub(crate) fn single(value: Value) -> QueryResult {
     Ok(vec![value])
 }
@@ -56,6 +241,25 @@ mod tests {
     use crate::query::{Executable, Query};
     use serde_json::Value;
 
+    /// Runs a query on a JSON input.
+    fn eval(query: &str, input: &str) -> crate::QueryResult {
+        let q: Query = query.parse().unwrap();
+        q.execute(&serde_json::from_str(input).unwrap())
+    }
+
+    /// The first result of running a query on a JSON input.
+    fn one(query: &str, input: &str) -> String {
+        eval(query, input).unwrap()[0].to_string()
+    }
+
+    /// Checks the first result of running each query on the input.
+    fn check(v: &Value, cases: &[(&str, &str)]) {
+        for (query, expected) in cases {
+            let q: Query = query.parse().unwrap();
+            assert_eq!(*expected, q.execute(v).unwrap()[0].to_string(), "{}", query);
+        }
+    }
+
     #[test]
     fn identity() {
         let q: Query = ".".parse().unwrap();
@@ -113,6 +317,15 @@ mod tests {
         let q: Query = ".[-2]".parse::<Query>().unwrap();
         let v: Value = serde_json::from_str(r#"[1,2,3]"#).unwrap();
         assert_eq!(r#"2"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".[-3], .[-4], .[3000000000], .[-9223372036854775808]"
+            .parse::<Query>()
+            .unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"1"#, r[0].to_string());
+        assert_eq!(r#"null"#, r[1].to_string());
+        assert_eq!(r#"null"#, r[2].to_string());
+        assert_eq!(r#"null"#, r[3].to_string());
     }
 
     #[test]
@@ -165,8 +378,14 @@ mod tests {
         assert_eq!(r#""jq""#, r[1].to_string());
         assert_eq!(r#""wikiflow""#, r[2].to_string());
 
-        //TODO: Splitting inside indexes still not supported
-        //let q: Query = ".[4,2]".parse::<Query>().unwrap();
+        let q: Query = ".[4,2]".parse().unwrap();
+        let v: Value = serde_json::from_str("[10, 20, 30, 40, 50]").unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(vec![Value::from(50), Value::from(30)], r);
+
+        let q: Query = ".[-1, 9, -9]".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(vec![Value::from(50), Value::Null, Value::Null], r);
     }
 
     #[test]
@@ -190,8 +409,20 @@ mod tests {
             q.execute(&v).unwrap()[0].to_string()
         );
 
-        //TODO: Numerical operations still not supported
-        //let q: Query = "[.[]|.*2]".parse().unwrap();
+        let q: Query = "[.[]|.*2]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[1,2,3]"#).unwrap();
+        assert_eq!(r#"[2,4,6]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[1+1, 2*3]".parse().unwrap();
+        assert_eq!(r#"[2,6]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[.x * 2]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"x": 4}"#).unwrap();
+        assert_eq!(r#"[8]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[.a * 2, .b + 1]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 1.5, "b": 2}"#).unwrap();
+        assert_eq!(r#"[3.0,3]"#, q.execute(&v).unwrap()[0].to_string());
     }
 
     #[test]
@@ -288,4 +519,1912 @@ mod tests {
         // assert_eq!(r#"1"#, r[0].to_string());
         // assert_eq!(r#"-1"#, r[1].to_string());
     }
+
+    #[test]
+    fn empty_and_error() {
+        let q: Query = "1, empty, 2".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"null"#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(2, r.len());
+        assert_eq!(r#"1"#, r[0].to_string());
+        assert_eq!(r#"2"#, r[1].to_string());
+
+        let q: Query = "[.[] | empty]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[1,2,3]"#).unwrap();
+        assert_eq!(r#"[]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "error".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""boom""#).unwrap();
+        assert_eq!("boom", q.execute(&v).unwrap_err().to_string());
+
+        let q: Query = r#"error("custom message")"#.parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 1}"#).unwrap();
+        assert_eq!("custom message", q.execute(&v).unwrap_err().to_string());
+
+        let q: Query = "error".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 1}"#).unwrap();
+        assert_eq!(
+            r#"{"a":1} (not a string)"#,
+            q.execute(&v).unwrap_err().to_string()
+        );
+
+        let q: Query = ".a | error?".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": "boom"}"#).unwrap();
+        assert!(q.execute(&v).unwrap().is_empty());
+    }
+
+    #[test]
+    fn environment() {
+        std::env::set_var("RQ_TEST_ENVIRONMENT", "bar");
+        let v: Value = serde_json::from_str(r#"null"#).unwrap();
+
+        let q: Query = "env.RQ_TEST_ENVIRONMENT".parse().unwrap();
+        assert_eq!(r#""bar""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "$ENV.RQ_TEST_ENVIRONMENT".parse().unwrap();
+        assert_eq!(r#""bar""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "$foo".parse().unwrap();
+        assert_eq!(
+            "$foo is not defined",
+            q.execute(&v).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn group_by() {
+        let q: Query = "group_by(.foo)".parse().unwrap();
+        let v: Value = serde_json::from_str(
+            r#"[{"foo":1, "bar":10}, {"foo":3, "bar":100}, {"foo":1, "bar":1}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            r#"[[{"bar":10,"foo":1},{"bar":1,"foo":1}],[{"bar":100,"foo":3}]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = "group_by(.k)".parse().unwrap();
+        let v: Value = serde_json::from_str(
+            r#"[{"k":"b","i":0}, {"k":"a","i":1}, {"k":"b","i":2}, {"k":"a","i":3}, {"k":"b","i":4}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            r#"[[{"i":1,"k":"a"},{"i":3,"k":"a"}],[{"i":0,"k":"b"},{"i":2,"k":"b"},{"i":4,"k":"b"}]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let v: Value = serde_json::from_str(r#"{"k":1}"#).unwrap();
+        assert!(q.execute(&v).is_err());
+
+        let q: Query = "[group_by(.a, .b)[] | length]".parse().unwrap();
+        let v: Value =
+            serde_json::from_str(r#"[{"a":1,"b":2},{"a":1,"b":1},{"a":1,"b":2}]"#).unwrap();
+        assert_eq!("[1,2]", q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn sort_by() {
+        let q: Query = "sort_by(.a, .b)".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[{"a":1,"b":2},{"a":1,"b":1}]"#).unwrap();
+        assert_eq!(
+            r#"[{"a":1,"b":1},{"a":1,"b":2}]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        // Elements with equal keys keep their order, and a missing key sorts first as null
+        let q: Query = "[sort_by(.a)[] | .i]".parse().unwrap();
+        let v: Value =
+            serde_json::from_str(r#"[{"a":2,"i":0},{"a":1,"i":1},{"i":2},{"a":1,"i":3}]"#).unwrap();
+        assert_eq!("[2,1,3,0]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "sort_by(empty)".parse().unwrap();
+        let v: Value = serde_json::from_str("[3,1,2]").unwrap();
+        assert_eq!("[3,1,2]", q.execute(&v).unwrap()[0].to_string());
+        assert!(q.execute(&Value::from("abc")).is_err());
+    }
+
+    #[test]
+    fn min_max() {
+        let v: Value = serde_json::from_str(
+            r#"[{"a":1,"b":2,"i":0},{"a":1,"b":1,"i":1},{"a":1,"b":1,"i":2},{"a":1,"b":2,"i":3}]"#,
+        )
+        .unwrap();
+        check(
+            &v,
+            &[
+                ("min_by(.a) | .i", "0"),
+                ("max_by(.a) | .i", "3"),
+                ("min_by(.a, .b) | .i", "1"),
+                ("max_by(.a, .b) | .i", "3"),
+                ("max_by(.b, 0 - .i) | .i", "0"),
+                ("[.[].b] | min", "1"),
+                ("[.[].b] | max", "2"),
+                ("[] | min", "null"),
+                ("[] | max_by(.a)", "null"),
+            ],
+        );
+
+        let q: Query = "max".parse().unwrap();
+        assert!(q.execute(&Value::from(1)).is_err());
+    }
+
+    #[test]
+    fn assignment() {
+        let q: Query = ".a = 5".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(r#"{"a":5}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".a = .b".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 1, "b": 2}"#).unwrap();
+        assert_eq!(r#"{"a":2,"b":2}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".a.b[1] = 1".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"null"#).unwrap();
+        assert_eq!(
+            r#"{"a":{"b":[null,1]}}"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = ".[] = 1".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[3, 4]"#).unwrap();
+        assert_eq!(r#"[1,1]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".a = .b[]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"b": [1, 2]}"#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"{"a":1,"b":[1,2]}"#, r[0].to_string());
+        assert_eq!(r#"{"a":2,"b":[1,2]}"#, r[1].to_string());
+
+        let q: Query = ".a + 1 = 1".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 1}"#).unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn any_all_short_circuit() {
+        let q: Query = "any(.[]; .flag)".parse().unwrap();
+        let v: Value =
+            serde_json::from_str(r#"[{"flag": false}, {"flag": true}, "boom"]"#).unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"[{"flag": false}, "boom"]"#).unwrap();
+        assert!(q.execute(&v).is_err());
+
+        let q: Query = "all(.[]; .flag)".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[{"flag": true}, {"flag": null}, "boom"]"#).unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"[{"flag": true}, {"flag": 0}]"#).unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "any(.[]; error)".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[]"#).unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"[1, 2]"#).unwrap();
+        let q: Query = r#"any(.[], error("x"); . == 1)"#.parse().unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = r#"all(.[], error("x"); . == 2)"#.parse().unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = r#"any(.[], error("x"); . == 3)"#.parse().unwrap();
+        assert!(q.execute(&v).is_err());
+        let q: Query = "any(repeat(1); . == 1)".parse().unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn any_all() {
+        let v: Value = serde_json::from_str(r#"[true, false]"#).unwrap();
+        let q: Query = "any".parse().unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "all".parse().unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"[]"#).unwrap();
+        let q: Query = "any".parse().unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "all".parse().unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"[{"a": 1}, {"a": null}]"#).unwrap();
+        let q: Query = "any(.a)".parse().unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "all(.a)".parse().unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"{"a": true, "b": 1}"#).unwrap();
+        let q: Query = "all".parse().unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn update_assignment() {
+        let q: Query = ".foo |= . + 1".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"foo": 42}"#).unwrap();
+        assert_eq!(r#"{"foo":43}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".[] |= empty".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[1, 2]"#).unwrap();
+        assert_eq!(r#"[1,2]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".a += 10".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 1}"#).unwrap();
+        assert_eq!(r#"{"a":11}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".foo += 1".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"bar": 42}"#).unwrap();
+        assert_eq!(
+            r#"{"bar":42,"foo":1}"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = ".[] *= .[0]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[2, 3, 4]"#).unwrap();
+        assert_eq!(r#"[4,6,8]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".a -= 1, .a /= 2, .a %= 5".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": 5}"#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"{"a":4}"#, r[0].to_string());
+        assert_eq!(r#"{"a":2.5}"#, r[1].to_string());
+        assert_eq!(r#"{"a":0}"#, r[2].to_string());
+    }
+
+    #[test]
+    fn logical_operators() {
+        let v: Value = serde_json::from_str(r#"{"a": 1, "b": null}"#).unwrap();
+
+        let q: Query = "false and error(\"boom\")".parse().unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "true or error(\"boom\")".parse().unwrap();
+        assert_eq!(r#"true"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "true and error(\"boom\")".parse().unwrap();
+        assert_eq!("boom", q.execute(&v).unwrap_err().to_string());
+
+        let q: Query = ".a and .b, .a or .b, .b or .b".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"false"#, r[0].to_string());
+        assert_eq!(r#"true"#, r[1].to_string());
+        assert_eq!(r#"false"#, r[2].to_string());
+
+        let q: Query = ".[] and true".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[0, false, null, "a"]"#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"[true,false,false,true]"#, Value::Array(r).to_string());
+
+        let q: Query = "false or true and false".parse().unwrap();
+        assert_eq!(r#"false"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn slice_unicode() {
+        let q: Query = ".[0:2]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""héllo""#).unwrap();
+        assert_eq!(r#""hé""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".[1:-1]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""a😀b😀c""#).unwrap();
+        assert_eq!(r#""😀b😀""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".[-2:]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""日本語""#).unwrap();
+        assert_eq!(r#""本語""#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn slice_reversed() {
+        let q: Query = ".[3:2]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"["a","b","c","d","e"]"#).unwrap();
+        assert_eq!(r#"[]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#""abcde""#).unwrap();
+        assert_eq!(r#""""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = ".[-1:-2]".parse().unwrap();
+        assert_eq!(r#""""#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn slice_edge_cases() {
+        let arr: Value = serde_json::from_str(r#"[0,1,2,3,4,5,6,7,8,9]"#).unwrap();
+        let s: Value = serde_json::from_str(r#""abcdefghij""#).unwrap();
+        for (query, from_arr, from_str) in &[
+            (".[5:2]", "[]", r#""""#),
+            (".[-1:-3]", "[]", r#""""#),
+            (".[-100:2]", "[0,1]", r#""ab""#),
+            (".[-3:-1]", "[7,8]", r#""hi""#),
+            (".[20:30]", "[]", r#""""#),
+            (".[-30:-20]", "[]", r#""""#),
+            (".[-12:3]", "[0,1,2]", r#""abc""#),
+            (".[8:-1]", "[8]", r#""i""#),
+            (".[3:-20]", "[]", r#""""#),
+            (".[10:]", "[]", r#""""#),
+            (".[-20:]", "[0,1,2,3,4,5,6,7,8,9]", r#""abcdefghij""#),
+            (".[:-10]", "[]", r#""""#),
+            (".[:0]", "[]", r#""""#),
+            (".[:100]", "[0,1,2,3,4,5,6,7,8,9]", r#""abcdefghij""#),
+        ] {
+            check(&arr, &[(query, from_arr)]);
+            check(&s, &[(query, from_str)]);
+        }
+
+        let q: Query = ".[1:2]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[]"#).unwrap();
+        assert_eq!(r#"[]"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn injected_variables() {
+        let mut vars = std::collections::HashMap::new();
+        vars.insert("x".to_string(), Value::from(2));
+        vars.insert("y".to_string(), Value::from("two"));
+
+        let q: Query = "{x: $x, y: $y}, .z + $x".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"z": 40}"#).unwrap();
+        let r = q.execute_with_vars(&v, &vars).unwrap();
+        assert_eq!(r#"{"x":2,"y":"two"}"#, r[0].to_string());
+        assert_eq!(r#"42"#, r[1].to_string());
+
+        let q: Query = "$ENV".parse().unwrap();
+        assert!(q.execute_with_vars(&v, &vars).unwrap()[0].is_object());
+    }
+
+    #[test]
+    fn number_literals() {
+        let q: Query = ". * 1e6, . + -0.5, . - .5".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"2"#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(2000000f64, r[0].as_f64().unwrap());
+        assert_eq!(1.5f64, r[1].as_f64().unwrap());
+        assert_eq!(1.5f64, r[2].as_f64().unwrap());
+    }
+
+    #[test]
+    fn reverse() {
+        let q: Query = "reverse".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[1,2,3]"#).unwrap();
+        assert_eq!(r#"[3,2,1]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#""abc""#).unwrap();
+        assert_eq!(r#""cba""#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#""héllo😀""#).unwrap();
+        assert_eq!(r#""😀olléh""#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"null"#).unwrap();
+        assert_eq!(r#"[]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#"{"a":1}"#).unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn object_construction_identity() {
+        let q: Query = "42 | {value: .}".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"null"#).unwrap();
+        assert_eq!(r#"{"value":42}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "{all: ., type: type}".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""hi""#).unwrap();
+        assert_eq!(
+            r#"{"all":"hi","type":"string"}"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let v: Value = serde_json::from_str(r#"{"a":[true]}"#).unwrap();
+        assert_eq!(
+            r#"{"all":{"a":[true]},"type":"object"}"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn types() {
+        let q: Query = "[.[] | type]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[0, false, [], {}, null, "hello"]"#).unwrap();
+        assert_eq!(
+            r#"["number","boolean","array","object","null","string"]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn first_last_nth() {
+        let v: Value = serde_json::from_str(r#"[10,20,30]"#).unwrap();
+        check(
+            &v,
+            &[
+                ("first", "10"),
+                ("last", "30"),
+                ("nth(1)", "20"),
+                ("nth(-1)", "30"),
+                ("nth(5)", "null"),
+                ("first(.[])", "10"),
+                ("last(.[])", "30"),
+                ("nth(2; .[])", "30"),
+            ],
+        );
+
+        let v: Value = serde_json::from_str(r#"[]"#).unwrap();
+        let q: Query = "first, last".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#"null"#, r[0].to_string());
+        assert_eq!(r#"null"#, r[1].to_string());
+
+        for query in &["first(.[])", "last(empty)", "nth(0; empty)", "nth(3; 1, 2)"] {
+            let q: Query = query.parse().unwrap();
+            assert!(q.execute(&v).unwrap().is_empty(), "{}", query);
+        }
+
+        let q: Query = "nth(-1; .[])".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+        let q: Query = "nth(\"a\")".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn limit() {
+        let v: Value = serde_json::from_str(r#"[1,2,3,4,5]"#).unwrap();
+        let q: Query = "[limit(2; .[])]".parse().unwrap();
+        assert_eq!(r#"[1,2]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[limit(10; .[])]".parse().unwrap();
+        assert_eq!(r#"[1,2,3,4,5]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[limit(0; .[])]".parse().unwrap();
+        assert_eq!(r#"[]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[limit(-1; .[])]".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn comparison() {
+        let v: Value = serde_json::from_str(r#"{"a": 1, "b": 2.0, "c": [1]}"#).unwrap();
+        check(
+            &v,
+            &[
+                (".a < .b", "true"),
+                (".a <= 1", "true"),
+                (".b > .a", "true"),
+                (".b >= 2", "true"),
+                (".a == 1.0", "true"),
+                (".a != .a", "false"),
+                (".c == [1]", "true"),
+                ("null < false", "true"),
+                (".c > \"z\"", "true"),
+                (".a + 1 == .b and .a < 2", "true"),
+            ],
+        );
+
+        assert!("1 < 2 < 3".parse::<Query>().is_err());
+        assert!("1 =< 2".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn while_until_repeat() {
+        let v: Value = serde_json::from_str(r#"1"#).unwrap();
+        let q: Query = "[while(. < 100; . * 2)]".parse().unwrap();
+        assert_eq!(
+            r#"[1,2,4,8,16,32,64]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = "until(. > 100; . * 2)".parse().unwrap();
+        assert_eq!(r#"128"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[while(. < 4; . + 1, . + 2)]".parse().unwrap();
+        assert_eq!(r#"[1,2,3,3]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "last(while(. < 200000; . + 1))".parse().unwrap();
+        assert_eq!(r#"199999"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[repeat(.[]?)]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[[1], 2]"#).unwrap();
+        assert_eq!(
+            r#"[[[1],2],[1],1,2]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        // Endless generators are only run as far as their results are taken
+        check(
+            &v,
+            &[
+                ("1 | [limit(3; repeat(1))]", "[1,1,1]"),
+                ("2 | first(repeat(1))", "2"),
+                ("1 | [limit(5; repeat(. * 2))]", "[1,2,4,8,16]"),
+                ("0 | [limit(3; recurse(. + 1))]", "[0,1,2]"),
+                ("0 | [limit(3; while(true; . + 1))]", "[0,1,2]"),
+            ],
+        );
+        let q: Query = "[limit(3; 1 | repeat(error))]".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn stream() {
+        let q: Query = ".a".parse().unwrap();
+        let input = r#"{"a": 1} {"a": [2]}"#;
+        let r: Vec<String> = crate::query_stream(&q, input.as_bytes())
+            .map(|v| v.unwrap().to_string())
+            .collect();
+        assert_eq!(vec!["1", "[2]"], r);
+
+        let q: Query = ".[]".parse().unwrap();
+        let r: Vec<_> = crate::query_stream(&q, "[1, 2] 3 x [4]".as_bytes()).collect();
+        assert_eq!(5, r.len());
+        assert_eq!("2", r[1].as_ref().unwrap().to_string());
+        assert!(matches!(r[2], Err(crate::RqError::Query(_))));
+        assert!(matches!(r[3], Err(crate::RqError::Input(_))));
+        assert_eq!("4", r[4].as_ref().unwrap().to_string());
+    }
+
+    #[test]
+    fn paths() {
+        let v: Value = serde_json::from_str(r#"{"a":{"b":1}}"#).unwrap();
+        let q: Query = "[paths]".parse().unwrap();
+        assert_eq!(
+            r#"[["a"],["a","b"]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let v: Value = serde_json::from_str(r#"[1,[[],{"a":2}]]"#).unwrap();
+        let q: Query = "[paths]".parse().unwrap();
+        assert_eq!(
+            r#"[[0],[1],[1,0],[1,1],[1,1,"a"]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = "[leaf_paths]".parse().unwrap();
+        assert_eq!(r#"[[0],[1,1,"a"]]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[paths(type == \"array\")]".parse().unwrap();
+        assert_eq!(r#"[[1],[1,0]]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[paths]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"1"#).unwrap();
+        assert_eq!(r#"[]"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn number_results() {
+        let v: Value = serde_json::from_str(r#"null"#).unwrap();
+        check(
+            &v,
+            &[
+                // A float operand gives a float, even when the result is whole
+                ("2 * 2.5", "5.0"),
+                ("2.0 + 1", "3.0"),
+                ("0.5 + 0.5", "1.0"),
+                ("1.5 - 0.25", "1.25"),
+                ("1e3 * 1", "1000.0"),
+                ("1e19 * 10", "1e+20"),
+                ("6.0 / 3", "2.0"),
+                // Integers stay integers unless they don't divide exactly
+                ("2 + 3", "5"),
+                ("10 / 4", "2.5"),
+                ("10 / 5", "2"),
+                ("7 % 2", "1"),
+                ("-7 % 2", "-1"),
+                // Integers that would overflow fall back to floats
+                ("9223372036854775807 + 1", "9.223372036854776e+18"),
+                ("-9223372036854775807 - 2", "-9.223372036854776e+18"),
+                ("4611686018427387904 * 2", "9.223372036854776e+18"),
+                ("4611686018427387904 * -2", "-9223372036854775808"),
+                ("-9223372036854775807 - 1", "-9223372036854775808"),
+            ],
+        );
+
+        for query in &["1 / 0", "1 % 0", "1.5 / 0.0", "1 % 0.0"] {
+            let q: Query = query.parse().unwrap();
+            assert!(q.execute(&v).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn format_strings() {
+        assert_eq!(r#""a&amp;b""#, one("@html", r#""a&b""#));
+        assert_eq!(
+            r#""&lt;p class=&#39;x&#39;&gt;&quot;hi&quot;&lt;/p&gt;""#,
+            one("@html", r#""<p class='x'>\"hi\"</p>""#)
+        );
+        assert_eq!(r#""[1,&quot;&lt;&quot;]""#, one("@html", r#"[1,"<"]"#));
+        assert_eq!(
+            r#""a%20b%2Fc%3Fd%3D%C3%A9~""#,
+            one("@uri", r#""a b/c?d=é~""#)
+        );
+        assert_eq!(r#""12""#, one("@uri", r#"12"#));
+        assert_eq!(r#""'it'\\''s'""#, one("@sh", r#""it's""#));
+        assert_eq!(
+            r#""'a b' 1 null false""#,
+            one("@sh", r#"["a b", 1, null, false]"#)
+        );
+        assert_eq!(r#""'x'""#, one(".[] | @sh", r#"["x"]"#));
+        assert_eq!(r#""NBSWY3DP""#, one("@base32", r#""hello""#));
+        assert_eq!(r#""GEZA====""#, one("@base32", r#"12"#));
+        assert_eq!(r#""hello""#, one("@base32d", r#""NBSWY3DP""#));
+        assert_eq!(
+            r#""héllo wörld""#,
+            one("@base32 | @base32d", r#""héllo wörld""#)
+        );
+        assert_eq!(r#""\"hi\"""#, one("@json", r#""hi""#));
+        assert_eq!(r#""{\"a\":[1,\"x\"]}""#, one("@json", r#"{"a": [1, "x"]}"#));
+        assert_eq!(r#""hi""#, one("tostring", r#""hi""#));
+        assert_eq!(
+            r#""{\"a\":[1,\"x\"]}""#,
+            one("tostring", r#"{"a": [1, "x"]}"#)
+        );
+        assert_eq!(r#""\"hi\"""#, one("tojson", r#""hi""#));
+        assert_eq!(r#""x\"y\"""#, one(r#""x\(.a | @json)""#, r#"{"a": "y"}"#));
+
+        let q: Query = "@sh".parse().unwrap();
+        for input in &[r#"{"a":1}"#, r#"[[1]]"#] {
+            let v: Value = serde_json::from_str(input).unwrap();
+            assert!(q.execute(&v).is_err());
+        }
+        let q: Query = "@base32d".parse().unwrap();
+        assert!(q.execute(&Value::from("hello!")).is_err());
+    }
+
+    #[test]
+    fn length() {
+        assert_eq!("0", one("length", "null"));
+        assert_eq!("5", one("length", r#""héllo""#));
+        assert_eq!("2", one("length", "[1,[2,3]]"));
+        assert_eq!("2", one("length", r#"{"a":1,"b":2}"#));
+        assert_eq!("5", one("length", "-5"));
+        assert_eq!("2.5", one("length", "-2.5"));
+
+        assert_eq!(
+            "boolean (true) has no length",
+            eval("length", "true").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn lenient_index() {
+        let strict = crate::scope::Scope::new();
+        let lenient = crate::scope::Scope::new().with_options(crate::scope::ExecOptions {
+            lenient_index: true,
+            ..Default::default()
+        });
+
+        let q: Query = ".foo".parse().unwrap();
+        let v: Value = serde_json::from_str("[1,2]").unwrap();
+        assert!(q.execute_in(&v, &strict).is_err());
+        assert_eq!("null", q.execute_in(&v, &lenient).unwrap()[0].to_string());
+
+        let q: Query = ".[0]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a":1}"#).unwrap();
+        assert!(q.execute_in(&v, &strict).is_err());
+        assert_eq!("null", q.execute_in(&v, &lenient).unwrap()[0].to_string());
+
+        // Slices of other types still error, as do well-typed lookups
+        let q: Query = ".[1:]".parse().unwrap();
+        assert!(q.execute_in(&v, &lenient).is_err());
+        let q: Query = ".a".parse().unwrap();
+        assert_eq!("1", q.execute_in(&v, &lenient).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn construction_operands() {
+        let v: Value = serde_json::from_str("null").unwrap();
+        let q: Query = "{a:1} + {b:2}".parse().unwrap();
+        assert_eq!(r#"{"a":1,"b":2}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "{a:1} * {a:{x:1}}".parse().unwrap();
+        assert_eq!(r#"{"a":{"x":1}}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let mut vars = std::collections::HashMap::new();
+        vars.insert(
+            "defaults".to_string(),
+            serde_json::from_str(r#"{"override":false,"depth":1}"#).unwrap(),
+        );
+        let q: Query = "$defaults + {override: true}".parse().unwrap();
+        assert_eq!(
+            r#"{"depth":1,"override":true}"#,
+            q.execute_with_vars(&v, &vars).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let v: Value = serde_json::from_str(r#"{"a": 1, "b": ["x", "y"]}"#).unwrap();
+        let q: Query = r#""a is \(.a), b is \(.b)""#.parse().unwrap();
+        assert_eq!(
+            r#""a is 1, b is [\"x\",\"y\"]""#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = r#""\(.b[]): \(.a, .a + 1)""#.parse().unwrap();
+        let r: Vec<String> = q
+            .execute(&v)
+            .unwrap()
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(vec![r#""x: 1""#, r#""y: 1""#, r#""x: 2""#, r#""y: 2""#], r);
+
+        let q: Query = r#""\(empty)""#.parse().unwrap();
+        assert!(q.execute(&v).unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_values() {
+        let q: Query = ".a + .b".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": "foo", "b": 1}"#).unwrap();
+        assert_eq!(
+            r#"Cannot add string ("foo") and number (1)"#,
+            q.execute(&v).unwrap_err().to_string()
+        );
+
+        let v: Value = serde_json::from_str(r#"{"a": {}, "b": [1]}"#).unwrap();
+        let q: Query = ".a - .b".parse().unwrap();
+        assert_eq!(
+            r#"Cannot subtract object ({}) and array ([1])"#,
+            q.execute(&v).unwrap_err().to_string()
+        );
+
+        let long = "x".repeat(100);
+        let v = Value::String(long);
+        let q: Query = ". - 1".parse().unwrap();
+        let e = q.execute(&v).unwrap_err().to_string();
+        assert!(e.contains(&format!(r#"string ("{}...)"#, "x".repeat(76))));
+    }
+
+    #[test]
+    fn object_construction_missing_key() {
+        let q: Query = "{foo}".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"bar":1}"#).unwrap();
+        assert_eq!(r#"{"foo":null}"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "{bar, foo}".parse().unwrap();
+        assert_eq!(
+            r#"{"bar":1,"foo":null}"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn lazy_execution() {
+        let q: Query = ".[] | .a".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[{"a": 1}, {"a": 2}, 3]"#).unwrap();
+        assert!(q.execute(&v).is_err());
+        let r: Vec<Value> = q.execute_iter(&v).take(2).map(|r| r.unwrap()).collect();
+        assert_eq!(r#"[1,2]"#, Value::Array(r).to_string());
+
+        let q: Query = ".[], error(\"boom\")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[1, 2]"#).unwrap();
+        let mut iter = q.execute_iter(&v);
+        assert_eq!("1", iter.next().unwrap().unwrap().to_string());
+        assert_eq!("2", iter.next().unwrap().unwrap().to_string());
+        assert!(iter.next().unwrap().is_err());
+
+        let q: Query = ".[] | .[] | . + 1".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[[1], {"a": 2}]"#).unwrap();
+        assert_eq!(
+            q.execute_in(&v, &crate::scope::Scope::new()).unwrap(),
+            q.execute(&v).unwrap()
+        );
+    }
+
+    #[test]
+    fn ascii_case() {
+        let v: Value = serde_json::from_str(r#""aB3!é""#).unwrap();
+        let q: Query = "ascii_upcase".parse().unwrap();
+        assert_eq!(r#""AB3!é""#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "ascii_downcase".parse().unwrap();
+        assert_eq!(r#""ab3!é""#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#""ÉÀ""#).unwrap();
+        assert_eq!(r#""ÉÀ""#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "ascii_upcase".parse().unwrap();
+        let v: Value = serde_json::from_str("42").unwrap();
+        assert_eq!(
+            "number cannot be uppercased",
+            q.execute(&v).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn recurse_large() {
+        let mut map = serde_json::Map::new();
+        for i in 0..5000 {
+            map.insert(format!("k{:04}", i), serde_json::json!({"v": [i]}));
+        }
+        let v = Value::Object(map);
+        let q: Query = "..".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(1 + 5000 * 3, r.len());
+        assert_eq!(v, r[0]);
+        assert_eq!(r#"{"v":[0]}"#, r[1].to_string());
+        assert_eq!(r#"[0]"#, r[2].to_string());
+        assert_eq!(r#"0"#, r[3].to_string());
+
+        let last = q.execute_iter(&v).last().unwrap().unwrap();
+        assert_eq!(r#"4999"#, last.to_string());
+    }
+
+    #[test]
+    fn reduce() {
+        let v: Value = serde_json::from_str("[10,2,5,3]").unwrap();
+        let q: Query = "reduce .[] as $item (0; . + $item)".parse().unwrap();
+        assert_eq!("20", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "reduce .[] as $x (0, 100; . + $x) | . * 2".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!("40", r[0].to_string());
+        assert_eq!("240", r[1].to_string());
+
+        assert!("reduce .[] as [$x] (0; empty)".parse::<Query>().is_err());
+
+        let q: Query = "reduce empty as $x (1; . + $x)".parse().unwrap();
+        assert_eq!("1", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "reduce .[] as $x (0; empty)".parse().unwrap();
+        assert_eq!("null", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "reduce .[] as $x (0; error(\"boom\"))".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn trimstr() {
+        let v: Value =
+            serde_json::from_str(r#"["fo", "foo", "barfoo", "foobar", "afoo"]"#).unwrap();
+        let q: Query = "[.[] | ltrimstr(\"foo\")]".parse().unwrap();
+        assert_eq!(
+            r#"["fo","","barfoo","bar","afoo"]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+        let q: Query = "[.[] | rtrimstr(\"foo\")]".parse().unwrap();
+        assert_eq!(
+            r#"["fo","","bar","foobar","a"]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        // Only a single occurrence is removed
+        let v: Value = serde_json::from_str(r#""ababc""#).unwrap();
+        let q: Query = "ltrimstr(\"ab\")".parse().unwrap();
+        assert_eq!(r#""abc""#, q.execute(&v).unwrap()[0].to_string());
+        let v: Value = serde_json::from_str(r#""cabab""#).unwrap();
+        let q: Query = "rtrimstr(\"ab\")".parse().unwrap();
+        assert_eq!(r#""cab""#, q.execute(&v).unwrap()[0].to_string());
+
+        let v: Value = serde_json::from_str(r#""ab""#).unwrap();
+        let q: Query = "ltrimstr(\"abcd\")".parse().unwrap();
+        assert_eq!(r#""ab""#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "ltrimstr(\"\"), rtrimstr(\"\")".parse().unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(r#""ab""#, r[0].to_string());
+        assert_eq!(r#""ab""#, r[1].to_string());
+
+        let v: Value = serde_json::from_str("1").unwrap();
+        let q: Query = "ltrimstr(\"1\")".parse().unwrap();
+        assert_eq!("1", q.execute(&v).unwrap()[0].to_string());
+        let v: Value = serde_json::from_str(r#""1""#).unwrap();
+        let q: Query = "ltrimstr(1)".parse().unwrap();
+        assert_eq!(r#""1""#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn has() {
+        let q: Query = "[.[] | has(\"foo\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[{"foo": 42}, {}]"#).unwrap();
+        assert_eq!("[true,false]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[.[] | has(2)]".parse().unwrap();
+        let v: Value = serde_json::from_str("[[0,1], [\"a\",\"b\",\"c\"]]").unwrap();
+        assert_eq!("[false,true]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "has(-1)".parse().unwrap();
+        assert_eq!("false", q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "has(\"a\")".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn in_container() {
+        let q: Query = "\"a\" | in({\"a\": 1})".parse().unwrap();
+        assert_eq!("true", q.execute(&Value::Null).unwrap()[0].to_string());
+        let q: Query = "\"b\" | in({\"a\": 1})".parse().unwrap();
+        assert_eq!("false", q.execute(&Value::Null).unwrap()[0].to_string());
+
+        let q: Query = "5 | in([1,2,3,4,5,6])".parse().unwrap();
+        assert_eq!("true", q.execute(&Value::Null).unwrap()[0].to_string());
+        let q: Query = "6 | in([1,2,3,4,5,6])".parse().unwrap();
+        assert_eq!("false", q.execute(&Value::Null).unwrap()[0].to_string());
+        let q: Query = "0 | in([])".parse().unwrap();
+        assert_eq!("false", q.execute(&Value::Null).unwrap()[0].to_string());
+
+        let q: Query = "\"a\" | in(1)".parse().unwrap();
+        assert_eq!(
+            "Cannot index number (1) with string",
+            q.execute(&Value::Null).unwrap_err().to_string()
+        );
+        let q: Query = "0 | in({})".parse().unwrap();
+        assert_eq!(
+            "Cannot index object ({}) with number",
+            q.execute(&Value::Null).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn iterate_null() {
+        let q: Query = ".[]".parse().unwrap();
+        assert_eq!(
+            "Cannot iterate over null (null)",
+            q.execute(&Value::Null).unwrap_err().to_string()
+        );
+        let r: Result<Vec<Value>, _> = q.execute_iter(&Value::Null).collect();
+        assert!(r.is_err());
+
+        let q: Query = ".[]?".parse().unwrap();
+        assert!(q.execute(&Value::Null).unwrap().is_empty());
+
+        let q: Query = ".a[]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"a": null}"#).unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn group_adjacent() {
+        let input = r#"{"k":"a","v":1} {"k":"a","v":2} {"k":"b","v":3} {"k":"a","v":4}"#;
+        let key: Query = ".k".parse().unwrap();
+        let groups = crate::group_adjacent(
+            crate::input::Inputs::new(input.as_bytes()),
+            key.clone(),
+            crate::scope::Scope::new(),
+        )
+        .map(|g| g.unwrap().to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                r#"[{"k":"a","v":1},{"k":"a","v":2}]"#,
+                r#"[{"k":"b","v":3}]"#,
+                r#"[{"k":"a","v":4}]"#,
+            ],
+            groups
+        );
+
+        let key: Query = ".k".parse().unwrap();
+        let mut groups = crate::group_adjacent(
+            crate::input::Inputs::new("{\"k\":1} 2".as_bytes()),
+            key.clone(),
+            crate::scope::Scope::new(),
+        );
+        assert!(matches!(
+            groups.next(),
+            Some(Err(crate::QueryError::Index(..)))
+        ));
+
+        let mut groups = crate::group_adjacent(
+            crate::input::Inputs::new(&b""[..]),
+            key.clone(),
+            crate::scope::Scope::new(),
+        );
+        assert!(groups.next().is_none());
+    }
+
+    #[test]
+    fn sorted_key_order() {
+        // Built in reverse order, so that it would iterate out of order if insertion order
+        // were preserved
+        let mut map = serde_json::Map::new();
+        map.insert("b".to_string(), Value::from(1));
+        map.insert("a".to_string(), Value::from(2));
+        map.insert("c".to_string(), Value::from(3));
+        let v = Value::Object(map);
+
+        let q: Query = ".[]".parse().unwrap();
+        let r: Vec<String> = q
+            .execute(&v)
+            .unwrap()
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(vec!["2", "1", "3"], r);
+        let r: Vec<String> = q.execute_iter(&v).map(|r| r.unwrap().to_string()).collect();
+        assert_eq!(vec!["2", "1", "3"], r);
+
+        let q: Query = "keys".parse().unwrap();
+        assert_eq!(r#"["a","b","c"]"#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "[paths]".parse().unwrap();
+        assert_eq!(
+            r#"[["a"],["b"],["c"]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+        let q: Query = "[..]".parse().unwrap();
+        assert_eq!(
+            r#"[{"a":2,"b":1,"c":3},2,1,3]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = "{b: 1, a: 2} | keys, [.[]]".parse().unwrap();
+        let r = q.execute(&Value::Null).unwrap();
+        assert_eq!(r#"["a","b"]"#, r[0].to_string());
+        assert_eq!("[2,1]", r[1].to_string());
+
+        let q: Query = "keys".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"["x","y"]"#).unwrap();
+        assert_eq!("[0,1]", q.execute(&v).unwrap()[0].to_string());
+        assert!(q.execute(&Value::Null).is_err());
+    }
+
+    #[test]
+    fn sort() {
+        let q: Query = "sort".parse().unwrap();
+        let v: Value =
+            serde_json::from_str(r#"[8, 3, null, 6, "b", {"a": 1}, [1], false, true, "a", 2.5]"#)
+                .unwrap();
+        assert_eq!(
+            r#"[null,false,true,2.5,3,6,8,"a","b",[1],{"a":1}]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        // Numerically equal integers and floats are ties, so they keep their input order
+        let q: Query = "[1.0, 1, 1.0] | sort".parse().unwrap();
+        assert_eq!(
+            "[1.0,1,1.0]",
+            q.execute(&Value::Null).unwrap()[0].to_string()
+        );
+        let q: Query = "[2, 1.0, 0, 1, 1.0] | sort".parse().unwrap();
+        assert_eq!(
+            "[0,1.0,1,1.0,2]",
+            q.execute(&Value::Null).unwrap()[0].to_string()
+        );
+
+        let q: Query = "sort".parse().unwrap();
+        assert!(q.execute(&Value::Null).is_err());
+    }
+
+    #[test]
+    fn split_string() {
+        let q: Query = "split(\", \")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""a, b,c, d, e, ""#).unwrap();
+        assert_eq!(
+            r#"["a","b,c","d","e",""]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+        let q: Query = "split(\"\")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""abc""#).unwrap();
+        assert_eq!(r#"["a","b","c"]"#, q.execute(&v).unwrap()[0].to_string());
+        let v: Value = serde_json::from_str(r#""""#).unwrap();
+        assert_eq!("[]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "split(\", *\"; null)".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""ab,cd, ef""#).unwrap();
+        assert_eq!(r#"["ab","cd","ef"]"#, q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "split(\"X\"; \"i\")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""axbXc""#).unwrap();
+        assert_eq!(r#"["a","b","c"]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "split(1)".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+        let q: Query = "split(\"a\")".parse().unwrap();
+        assert!(q.execute(&Value::from(1)).is_err());
+    }
+
+    #[test]
+    fn splits() {
+        let q: Query = "[splits(\"[0-9]\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""a1b2c""#).unwrap();
+        assert_eq!(r#"["a","b","c"]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "splits(\", *\"; null)".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""ab,cd, ef""#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!(3, r.len());
+        assert_eq!(r#""ef""#, r[2].to_string());
+
+        let q: Query = "[splits(\"b\"; \"gi\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""aBcbd""#).unwrap();
+        assert_eq!(r#"["a","c","d"]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "splits(\"(\")".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+        let q: Query = "splits(\"a\"; \"q\")".parse().unwrap();
+        assert_eq!(
+            "q is not a valid modifier string",
+            q.execute(&v).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_regex() {
+        let q: Query = "test(\"foo\")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""foo bar""#).unwrap();
+        assert_eq!("true", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[.[] | test(\"a b c # spaces are ignored\"; \"ix\")]"
+            .parse()
+            .unwrap();
+        let v: Value = serde_json::from_str(r#"["xabcd", "ABC"]"#).unwrap();
+        assert_eq!("[true,true]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "test(\"^b\")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""abc""#).unwrap();
+        assert_eq!("false", q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn indices() {
+        let q: Query = "indices(\",\")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""a,b,c,d""#).unwrap();
+        assert_eq!("[1,3,5]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "indices(\", \")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""a,b, cd, efg, hijk""#).unwrap();
+        assert_eq!("[3,7,12]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "indices(\"aa\"), indices(\"é\"), indices(\"\")"
+            .parse()
+            .unwrap();
+        let v: Value = serde_json::from_str(r#""éaaaé""#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!("[1,2]", r[0].to_string());
+        assert_eq!("[0,4]", r[1].to_string());
+        assert_eq!("null", r[2].to_string());
+
+        let q: Query = "indices(1), indices([1,2]), indices([])".parse().unwrap();
+        let v: Value = serde_json::from_str("[0,1,2,1,3,1,2]").unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!("[1,3,5]", r[0].to_string());
+        assert_eq!("[1,5]", r[1].to_string());
+        assert_eq!("null", r[2].to_string());
+
+        let q: Query = "indices(1)".parse().unwrap();
+        assert_eq!("null", q.execute(&Value::Null).unwrap()[0].to_string());
+        assert!(q.execute(&Value::from("1")).is_err());
+    }
+
+    #[test]
+    fn index_rindex() {
+        let q: Query = "index(\", \"), rindex(\", \"), index(\"x\"), rindex(\"x\")"
+            .parse()
+            .unwrap();
+        let v: Value = serde_json::from_str(r#""a,b, cd, efg, hijk""#).unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!("3", r[0].to_string());
+        assert_eq!("12", r[1].to_string());
+        assert_eq!("null", r[2].to_string());
+        assert_eq!("null", r[3].to_string());
+
+        let q: Query = "index(1), rindex(1), index([1,2]), rindex([1,2])"
+            .parse()
+            .unwrap();
+        let v: Value = serde_json::from_str("[0,1,2,1,3,1,2]").unwrap();
+        let r = q.execute(&v).unwrap();
+        assert_eq!("1", r[0].to_string());
+        assert_eq!("5", r[1].to_string());
+        assert_eq!("1", r[2].to_string());
+        assert_eq!("5", r[3].to_string());
+    }
+
+    #[test]
+    fn capture() {
+        let q: Query = "capture(\"(?<y>[0-9]{4})\")".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""2020""#).unwrap();
+        assert_eq!(r#"{"y":"2020"}"#, q.execute(&v).unwrap()[0].to_string());
+
+        // Unnamed groups are left out and named groups that didn't match are null
+        let q: Query = "capture(\"(?<a>[a-z]+)-(?<n>[0-9]+)(-)?(?<x>x)?\")"
+            .parse()
+            .unwrap();
+        let v: Value = serde_json::from_str(r#""xyzzy-14""#).unwrap();
+        assert_eq!(
+            r#"{"a":"xyzzy","n":"14","x":null}"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = "[capture(\"(?<d>[0-9])\"; \"g\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""a1b2""#).unwrap();
+        assert_eq!(
+            r#"[{"d":"1"},{"d":"2"}]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = "[capture(\"(?<d>[0-9])\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""abc""#).unwrap();
+        assert_eq!("[]", q.execute(&v).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn scan() {
+        let q: Query = "[scan(\"[0-9]+\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""ab12cd34""#).unwrap();
+        assert_eq!(r#"["12","34"]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[scan(\"c\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""abcdefabc""#).unwrap();
+        assert_eq!(r#"["c","c"]"#, q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[scan(\"([a-z])([0-9])?\"; \"i\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""a1B""#).unwrap();
+        assert_eq!(
+            r#"[["a","1"],["B",null]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        let q: Query = "[scan(\"aa\")]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#""aaaaa""#).unwrap();
+        assert_eq!(r#"["aa","aa"]"#, q.execute(&v).unwrap()[0].to_string());
+    }
+
+    fn input_stream(values: &[&str]) -> crate::scope::InputStream {
+        let values: Vec<_> = values
+            .iter()
+            .map(|v| Ok(serde_json::from_str(v).unwrap()))
+            .collect();
+        // Reading past the given values means an input was consumed eagerly
+        let exhausted = std::iter::from_fn(|| panic!("Read past the end of the inputs"));
+        crate::scope::InputStream::new(values.into_iter().chain(exhausted))
+    }
+
+    #[test]
+    fn inputs() {
+        let scope = crate::scope::Scope::new().with_inputs(input_stream(&["1", "2", "3"]));
+        let q: Query = "[limit(3; inputs)]".parse().unwrap();
+        assert_eq!(
+            "[1,2,3]",
+            q.execute_in(&Value::Null, &scope).unwrap()[0].to_string()
+        );
+
+        let scope = crate::scope::Scope::new().with_inputs(input_stream(&["1", "2"]));
+        let q: Query = "input, [., input]".parse().unwrap();
+        let r = q.execute_in(&Value::Null, &scope).unwrap();
+        assert_eq!("1", r[0].to_string());
+        assert_eq!("[null,2]", r[1].to_string());
+
+        let q: Query = "input".parse().unwrap();
+        let scope = crate::scope::Scope::new();
+        assert_eq!(
+            "No more inputs",
+            q.execute_in(&Value::Null, &scope).unwrap_err().to_string()
+        );
+        let q: Query = "[inputs]".parse().unwrap();
+        assert_eq!(
+            "[]",
+            q.execute_in(&Value::Null, &scope).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn reduce_inputs() {
+        let scope = crate::scope::Scope::new().with_inputs(input_stream(&["1", "2", "3"]));
+        let q: Query = "reduce limit(3; inputs) as $x (0; . + $x)".parse().unwrap();
+        assert_eq!(
+            "6",
+            q.execute_in(&Value::Null, &scope).unwrap()[0].to_string()
+        );
+
+        // Inputs are consumed one at a time, so the failing update stops reading them
+        let scope = crate::scope::Scope::new().with_inputs(input_stream(&["1", "\"a\""]));
+        let q: Query = "reduce inputs as $x (0; . + $x)".parse().unwrap();
+        assert!(q.execute_in(&Value::Null, &scope).is_err());
+    }
+
+    #[test]
+    fn loc() {
+        let q: Query = "$__loc__".parse().unwrap();
+        assert_eq!(
+            r#"{"file":"<stdin>","line":1}"#,
+            q.execute(&Value::Null).unwrap()[0].to_string()
+        );
+        let q: Query = "\"\n\" | $__loc__.line".parse().unwrap();
+        assert_eq!("2", q.execute(&Value::Null).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn debug_messages() {
+        let messages = crate::scope::Messages::new();
+        let scope = crate::scope::Scope::new().with_messages(messages.clone());
+        let q: Query = "[.[] | debug | stderr]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"[1, "a"]"#).unwrap();
+        assert_eq!(
+            r#"[1,"a"]"#,
+            q.execute_in(&v, &scope).unwrap()[0].to_string()
+        );
+        assert_eq!(
+            "[\"DEBUG:\",1]\n1[\"DEBUG:\",\"a\"]\n\"a\"",
+            String::from_utf8(messages.take()).unwrap()
+        );
+        assert!(messages.take().is_empty());
+    }
+
+    #[test]
+    fn recurse_deep() {
+        // jq's 100k levels can't be reached here: serde_json clones and drops values one frame
+        // per level, so in a debug build even `length` overflows 8MB by 20k levels, and `[..]`
+        // and `[paths]` grow with the square of the depth. Instead, on a fixed 2MB stack, 2000
+        // levels is enough to overflow the recursive `paths` this replaced.
+        let depth = 2000;
+        let run = move || {
+            let mut v = Value::from(0);
+            for _ in 0..depth {
+                v = Value::Array(vec![v]);
+            }
+
+            let q: Query = "[..] | length".parse().unwrap();
+            assert_eq!(Value::from(depth + 1), q.execute(&v).unwrap()[0]);
+            let q: Query = "[recurse] | length".parse().unwrap();
+            assert_eq!(Value::from(depth + 1), q.execute(&v).unwrap()[0]);
+            let q: Query = "[recurse(.[0]?)] | length".parse().unwrap();
+            assert_eq!(Value::from(depth + 1), q.execute(&v).unwrap()[0]);
+            let q: Query = "[paths] | length".parse().unwrap();
+            assert_eq!(Value::from(depth), q.execute(&v).unwrap()[0]);
+            let q: Query = "last(..)".parse().unwrap();
+            assert_eq!(Value::from(0), q.execute(&v).unwrap()[0]);
+        };
+        std::thread::Builder::new()
+            .stack_size(2 * 1024 * 1024)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn recurse_function() {
+        let v: Value = serde_json::from_str(r#"{"a":[1,{"b":2}]}"#).unwrap();
+        let q: Query = "[recurse] == [..]".parse().unwrap();
+        assert_eq!(Value::Bool(true), q.execute(&v).unwrap()[0]);
+
+        let q: Query = "[2 | recurse(. * .; . < 100)]".parse().unwrap();
+        assert_eq!("[2,4,16]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "[.a | recurse(.[]?)]".parse().unwrap();
+        assert_eq!(
+            r#"[[1,{"b":2}],1,{"b":2},2]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn streaming() {
+        let v: Value = serde_json::from_str(r#"{"a":[1,2]}"#).unwrap();
+        let q: Query = "[tostream]".parse().unwrap();
+        assert_eq!(
+            r#"[[["a",0],1],[["a",1],2],[["a",1]],[["a"]]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+        let q: Query = "fromstream(tostream)".parse().unwrap();
+        assert_eq!(vec![v.clone()], q.execute(&v).unwrap());
+
+        let v: Value = serde_json::from_str(r#"[{"b":{},"a":[]},3,"x"]"#).unwrap();
+        let q: Query = "[tostream]".parse().unwrap();
+        assert_eq!(
+            r#"[[[0,"a"],[]],[[0,"b"],{}],[[0,"b"]],[[1],3],[[2],"x"],[[2]]]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+        let q: Query = "fromstream(tostream)".parse().unwrap();
+        assert_eq!(vec![v.clone()], q.execute(&v).unwrap());
+
+        // Each top-level element is reassembled on its own
+        let q: Query = "[fromstream(.[] | tostream)]".parse().unwrap();
+        assert_eq!(v, q.execute(&v).unwrap()[0]);
+        let q: Query = "[1 | tostream]".parse().unwrap();
+        assert_eq!("[[[],1]]", q.execute(&v).unwrap()[0].to_string());
+
+        let q: Query = "fromstream(1)".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(r#""1970-01-01T00:00:00Z""#, one("todate", "0"));
+        assert_eq!(r#""2015-03-05T23:51:47Z""#, one("todate", "1425599507.9"));
+        assert_eq!("1425599507", one("fromdate", r#""2015-03-05T23:51:47Z""#));
+        assert_eq!("1425599507", one("todate | fromdate", "1425599507"));
+        assert_eq!(
+            r#""Thu, 05 Mar 2015""#,
+            one(r#"strftime("%a, %d %b %Y")"#, "1425599507")
+        );
+        assert_eq!(
+            "[2015,2,5,23,51,47,4,63]",
+            one(
+                r#"strptime("%Y-%m-%dT%H:%M:%SZ")"#,
+                r#""2015-03-05T23:51:47Z""#
+            )
+        );
+        assert_eq!(
+            "[2015,2,5,22,51,47,4,63]",
+            one(
+                r#"strptime("%Y-%m-%d %H:%M:%S %z")"#,
+                r#""2015-03-05 23:51:47 +0100""#
+            )
+        );
+        assert_eq!(
+            "[2016,1,29,0,0,0,1,59]",
+            one(r#"strptime("%Y-%m-%d")"#, r#""2016-02-29""#)
+        );
+        assert_eq!(
+            Value::Bool(true),
+            eval("now > 1600000000", "null").unwrap()[0]
+        );
+
+        assert!(matches!(
+            eval("fromdate", r#""yesterday""#),
+            Err(crate::QueryError::Date(_, _))
+        ));
+        assert!(matches!(
+            eval(r#"strftime("%Q")"#, "0"),
+            Err(crate::QueryError::DateFormat(_))
+        ));
+        assert!(matches!(
+            eval(r#"strptime("%Q")"#, r#""x""#),
+            Err(crate::QueryError::DateFormat(_))
+        ));
+        assert!(eval("todate", r#""0""#).is_err());
+        assert!(eval("fromdate", "0").is_err());
+        assert!(eval("todate", "1e300").is_err());
+    }
+
+    #[test]
+    fn broken_down_times() {
+        assert_eq!("1609459200", one("gmtime | mktime", "1609459200"));
+        assert_eq!("[2020,11,31,23,59,59,4,365]", one("gmtime", "1609459199"));
+        assert_eq!("[2016,1,29,12,0,0,1,59]", one("gmtime", "1456747200"));
+        assert_eq!("[1970,0,1,0,0,1.5,4,0]", one("gmtime", "1.5"));
+        assert_eq!("[1969,11,31,23,59,59,3,364]", one("gmtime", "-1"));
+
+        // Months count from 0 and overflowing fields carry over
+        assert_eq!("1456747200", one("mktime", "[2016,1,29,12,0,0,0,0]"));
+        assert_eq!("1456790400", one("mktime", "[2016,1,30,0,0,0,0,0]"));
+        assert_eq!("1483228800", one("mktime", "[2016,12,1,0,0,0]"));
+        assert_eq!("0", one("mktime", "[1970,0,1,0,0,0.9,4,0]"));
+
+        assert_eq!(
+            r#""2016-02-29T12:00:00Z""#,
+            one("todate", "[2016,1,29,12,0,0,1,59]")
+        );
+        assert_eq!(
+            r#""Monday""#,
+            one(r#"gmtime | strftime("%A")"#, "1456747200")
+        );
+        assert_eq!(
+            "1425599507",
+            one(
+                r#"strptime("%Y-%m-%dT%H:%M:%SZ") | mktime"#,
+                r#""2015-03-05T23:51:47Z""#
+            )
+        );
+
+        assert!(eval("mktime", "0").is_err());
+        assert!(eval("mktime", "[2016,1]").is_err());
+        assert!(eval("mktime", r#"[2016,1,1,0,0,"0"]"#).is_err());
+        assert!(eval("gmtime", "[]").is_err());
+    }
+
+    #[test]
+    fn delete_paths() {
+        assert_eq!(r#"{"b":2}"#, one("del(.a)", r#"{"a":1,"b":2}"#));
+        assert_eq!(
+            r#"{"a":{}}"#,
+            one("del(.a.b, .c)", r#"{"a":{"b":1},"c":2}"#)
+        );
+        assert_eq!("null", one("del(.)", "[1]"));
+        assert_eq!("null", one("del(.a)", "null"));
+
+        // Every path is resolved against the original array
+        assert_eq!(
+            r#"["a","d"]"#,
+            one("del(.[1], .[2])", r#"["a","b","c","d"]"#)
+        );
+        assert_eq!(
+            r#"["a","c"]"#,
+            one("del(.[1], .[-1])", r#"["a","b","c","d"]"#)
+        );
+        assert_eq!("[0,3,4,5]", one("del(.[1:3])", "[0,1,2,3,4,5]"));
+        assert_eq!("[0,4,5]", one("del(.[1:3], .[2:4])", "[0,1,2,3,4,5]"));
+        assert_eq!("[0,1,2,3]", one("del(.[-2:])", "[0,1,2,3,4,5]"));
+        assert_eq!("[]", one("del(.[0:])", "[0,1,2,3,4,5]"));
+        assert_eq!("[0,1,2,3,4,5]", one("del(.[4:2], .[9])", "[0,1,2,3,4,5]"));
+        assert_eq!("[0,2,3,4,5]", one("del(.[1:3][0])", "[0,1,2,3,4,5]"));
+        assert_eq!("[0,1,4,5]", one("del(.[1:][1:3])", "[0,1,2,3,4,5]"));
+        assert_eq!(
+            r#"[{"a":[2]},{"a":[1,2]}]"#,
+            one("del(.[0].a[0])", r#"[{"a":[1,2]},{"a":[1,2]}]"#)
+        );
+
+        assert_eq!("[0,3]", one("delpaths([[1], [2]])", "[0,1,2,3]"));
+        assert_eq!("5", one("del(empty)", "5"));
+        assert_eq!("5", one("delpaths([])", "5"));
+        assert_eq!(r#""s""#, one("del(.[]?)", r#""s""#));
+        assert_eq!("true", one("del(.a?)", "true"));
+        assert!(eval("del(.a)", "5").is_err());
+        assert_eq!(
+            "[0,3]",
+            one(r#"delpaths([[{"start": 1, "end": 3}]])"#, "[0,1,2,3]")
+        );
+        assert_eq!("2", one(r#"getpath(["a", 1])"#, r#"{"a":[1,2]}"#));
+        assert_eq!("null", one(r#"getpath(["a", "b"])"#, "null"));
+        assert_eq!(
+            "[2,3]",
+            one(r#"getpath([{"start": 1, "end": null}])"#, "[1,2,3]")
+        );
+        assert_eq!(r#"[1,"x",3]"#, one(r#".[1:2] = ["x"]"#, "[1,2,3]"));
+        assert_eq!(r#"["x"]"#, one(r#".[0:] = ["x"]"#, "[1,2,3]"));
+
+        assert!(eval("del(.[1:3])", r#"{"a":1}"#).is_err());
+        assert!(eval("del(.a)", "[1]").is_err());
+        assert!(eval("del(.[0])", r#"{"a":1}"#).is_err());
+        assert!(eval("del(1)", "[1]").is_err());
+        assert!(eval(r#"getpath("a")"#, r#"{"a":1}"#).is_err());
+        assert!(eval(r#"getpath([{"start": "a"}])"#, "[1]").is_err());
+        assert!(eval(r#"delpaths(["a"])"#, r#"{"a":1}"#).is_err());
+        assert!(eval(r#".[1:2] = "x""#, "[1,2,3]").is_err());
+    }
+
+    #[test]
+    fn type_filters() {
+        let v: Value = serde_json::from_str(r#"[null,true,1,"a",[2],{"b":3},false,4.5]"#).unwrap();
+        check(
+            &v,
+            &[
+                ("[.[] | nulls]", "[null]"),
+                ("[.[] | booleans]", "[true,false]"),
+                ("[.[] | numbers]", "[1,4.5]"),
+                ("[.[] | strings]", r#"["a"]"#),
+                ("[.[] | arrays]", "[[2]]"),
+                ("[.[] | objects]", r#"[{"b":3}]"#),
+                ("[.[] | iterables]", r#"[[2],{"b":3}]"#),
+                ("[.[] | scalars]", r#"[null,true,1,"a",false,4.5]"#),
+                ("[.[] | values]", r#"[true,1,"a",[2],{"b":3},false,4.5]"#),
+                ("[.. | numbers]", "[1,2,3,4.5]"),
+            ],
+        );
+    }
+
+    #[test]
+    fn abs_and_toarray() {
+        assert_eq!("5", one("abs", "-5"));
+        assert_eq!("5", one("abs", "5"));
+        assert_eq!("0.5", one("abs", "-0.5"));
+        assert_eq!("9223372036854775808", one("abs", "-9223372036854775808"));
+        assert_eq!("18446744073709551615", one("abs", "18446744073709551615"));
+        assert_eq!("[3]", one("toarray", "3"));
+        assert_eq!("[3]", one("toarray", "[3]"));
+        assert_eq!("[null]", one("toarray", "null"));
+        assert_eq!(r#"[{"a":1}]"#, one("toarray", r#"{"a":1}"#));
+
+        let q: Query = "abs".parse().unwrap();
+        assert!(q.execute(&Value::from("abc")).is_err());
+        assert!(q.execute(&Value::Null).is_err());
+    }
+
+    #[test]
+    fn negative_index_assignment() {
+        assert_eq!("[1,2,9]", one(".[-1] = 9", "[1,2,3]"));
+        assert_eq!("[9,2,3]", one(".[-3] = 9", "[1,2,3]"));
+        assert_eq!("[1,2,4]", one(".[-1] |= . + 1", "[1,2,3]"));
+        assert_eq!("[1,2,13]", one(".[-1] += 10", "[1,2,3]"));
+        assert_eq!(r#"{"a":[0,2]}"#, one(".a[-2] = 0", r#"{"a":[1,2]}"#));
+        assert_eq!(r#"[{"b":1}]"#, one(".[-1].b = 1", r#"[{"b":0}]"#));
+
+        let v: Value = serde_json::from_str("[1,2,3]").unwrap();
+        for query in &[".[-4] = 9", ".[-4] |= 9", ".[-1][-1] = 9"] {
+            let q: Query = query.parse().unwrap();
+            assert!(q.execute(&v).is_err(), "{}", query);
+        }
+        let q: Query = ".[-1] = 9".parse().unwrap();
+        assert!(q.execute(&Value::Null).is_err());
+
+        for query in &[".[1000000000] = 1", ".a[536870912] |= 1"] {
+            let q: Query = query.parse().unwrap();
+            let e = q.execute(&Value::Null).unwrap_err();
+            assert_eq!("Array index too large", e.to_string(), "{}", query);
+        }
+        let q: Query = ".[3] = 1".parse().unwrap();
+        assert_eq!(
+            "[null,null,null,1]",
+            q.execute(&Value::Null).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn error_messages() {
+        let message = |q: &str, v: &str| {
+            let q: Query = q.parse().unwrap();
+            let v: Value = serde_json::from_str(v).unwrap();
+            q.execute(&v).unwrap_err().to_string()
+        };
+        assert_eq!("Cannot iterate over number (3)", message(".[]", "3"));
+        assert_eq!(
+            "Cannot iterate over boolean (true)",
+            message(".a[]", r#"{"a":true}"#)
+        );
+        assert_eq!(
+            r#"Cannot index string ("abc") with string"#,
+            message(".a", r#""abc""#)
+        );
+        assert_eq!(
+            r#"Cannot index object ({"a":1}) with number"#,
+            message(".[0]", r#"{"a":1}"#)
+        );
+        assert_eq!("Cannot index number (1) with slice", message(".[1:]", "1"));
+        assert_eq!(
+            "Cannot index array ([1,2]) with string",
+            message(r#"nth("a")"#, "[1,2]")
+        );
+        // Only the part of the array a slice selects is described
+        assert_eq!(
+            "Cannot index array ([2,3]) with boolean",
+            message(
+                r#"delpaths([[{"start": 1, "end": null}, true]])"#,
+                "[1,2,3]"
+            )
+        );
+        assert_eq!(
+            "Cannot use number (1) as object key",
+            message("{(.a): 2}", r#"{"a":1}"#)
+        );
+
+        let long = format!("[{}]", vec!["1"; 100].join(","));
+        let m = message(".a", &long);
+        assert!(m.starts_with("Cannot index array ([1,1,1,"), "{}", m);
+        assert!(m.ends_with("...) with string"), "{}", m);
+    }
+
+    #[test]
+    fn string_repeat_and_null() {
+        let v: Value = serde_json::from_str(r#"{"o":{"a":1},"s":"ab"}"#).unwrap();
+        check(
+            &v,
+            &[
+                (".s * 3", r#""ababab""#),
+                (".s * 1", r#""ab""#),
+                (".s * 0.5", r#""ab""#),
+                (".s * 1.5", r#""abab""#),
+                (".s * 0", "null"),
+                (".s * -1", "null"),
+                (".s * -0.5", "null"),
+                (".o + null", r#"{"a":1}"#),
+                ("null + .o", r#"{"a":1}"#),
+                (".s + null", r#""ab""#),
+                ("null + 1", "1"),
+                ("null + null", "null"),
+            ],
+        );
+
+        for query in &[".s * 1e300", ".s * 1e15"] {
+            let q: Query = query.parse().unwrap();
+            let e = q.execute(&v).unwrap_err();
+            assert_eq!("Repeat string result too long", e.to_string(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn contains_and_inside() {
+        let v: Value = serde_json::from_str(r#"{"a":{"b":1,"c":2}}"#).unwrap();
+        check(
+            &v,
+            &[
+                (r#"contains({"a":{"b":1}})"#, "true"),
+                (r#"contains({"a":{"b":2}})"#, "false"),
+                (r#"{"a":{"b":1}} | inside({"a":{"b":1,"c":2}})"#, "true"),
+                (r#""bar" | inside("foobar")"#, "true"),
+                (
+                    r#"["baz", "bar"] | inside(["foobar", "foobaz", "blarp"])"#,
+                    "true",
+                ),
+                (
+                    r#"["bazzzz", "bar"] | inside(["foobar", "foobaz", "blarp"])"#,
+                    "false",
+                ),
+                (r#"[.a.b, 3] | contains([1])"#, "true"),
+                (r#"[1, "a"] | contains(["a"])"#, "true"),
+                (r#"{"a": 1} | contains({"a": "x"})"#, "false"),
+            ],
+        );
+
+        let q: Query = "contains([1])".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn delete_in_one_pass() {
+        let v: Value = serde_json::from_str(r#"{"a":1,"b":2,"c":3}"#).unwrap();
+        check(
+            &v,
+            &[
+                ("del(.a, .b)", r#"{"c":3}"#),
+                ("del(.a, .a)", r#"{"b":2,"c":3}"#),
+                ("del(.a, .d)", r#"{"b":2,"c":3}"#),
+                ("del(.[])", "{}"),
+                ("[.[]] | del(.[0], .[1])", "[3]"),
+                ("[.[]] | del(.[], .[0])", "[]"),
+            ],
+        );
+    }
+
+    #[test]
+    fn special_numbers() {
+        check(
+            &Value::Null,
+            &[
+                ("infinite > 1e308", "true"),
+                ("infinite", "1.7976931348623157e+308"),
+                ("nan", "null"),
+                ("nan < 1", "true"),
+                ("[infinite, 1] | [.[] | isinfinite]", "[true,false]"),
+                (
+                    "[1, 0, 1e-310, infinite] | [.[] | isnormal]",
+                    "[true,false,false,false]",
+                ),
+            ],
+        );
+
+        for query in &[r#""a" | isinfinite"#, "true | isnormal", "nan | isnan"] {
+            let q: Query = query.parse().unwrap();
+            assert!(q.execute(&Value::Null).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn alternative_assignment() {
+        assert_eq!(r#"{"a":5}"#, one(".a //= 5", r#"{"a":null}"#));
+        assert_eq!(r#"{"a":1}"#, one(".a //= 5", r#"{"a":1}"#));
+        assert_eq!(r#"{"a":5}"#, one(".a //= 5", r#"{"a":false}"#));
+        assert_eq!(r#"{"a":5}"#, one(".a //= 5", r#"{}"#));
+        assert_eq!(r#"{"a":2,"b":2}"#, one(".a //= .b", r#"{"b":2}"#));
+        assert_eq!("[1,0,0,true]", one(".[] //= 0", r#"[1,null,false,true]"#));
+        assert_eq!(r#"{"a":{"b":5}}"#, one(".a.b //= 5", "null"));
+
+        let q: Query = "[.a //= .b[]]".parse().unwrap();
+        let v: Value = serde_json::from_str(r#"{"b":[1,2]}"#).unwrap();
+        assert_eq!(
+            r#"[{"a":1,"b":[1,2]},{"a":2,"b":[1,2]}]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn multiple_keys() {
+        let v: Value = serde_json::from_str(r#"{"a":1,"b":2,"c":{"d":3}}"#).unwrap();
+        check(
+            &v,
+            &[
+                (r#"[.["a","b"]]"#, "[1,2]"),
+                (r#"[.["b", "a", "b"]]"#, "[2,1,2]"),
+                (r#"[.["a","x"]]"#, "[1,null]"),
+                (r#"[.c["d","e"]]"#, "[3,null]"),
+                (r#"[.["x","y"]?]"#, "[null,null]"),
+                (r#".["a","b"] |= . + 10"#, r#"{"a":11,"b":12,"c":{"d":3}}"#),
+                (r#"del(.["a","c"])"#, r#"{"b":2}"#),
+            ],
+        );
+
+        let q: Query = r#".["a","b"]"#.parse().unwrap();
+        assert!(q.execute(&Value::from(1)).is_err());
+        let q: Query = r#"[.["a","b"]?]"#.parse().unwrap();
+        assert_eq!("[]", q.execute(&Value::from(1)).unwrap()[0].to_string());
+    }
+
+    #[test]
+    fn escaped_keys() {
+        let v: Value = serde_json::from_str(r#"{"a\"b":1,"tab\tkey":2}"#).unwrap();
+        check(
+            &v,
+            &[
+                (r#".["a\"b"]"#, "1"),
+                (r#".["tab\tkey"]"#, "2"),
+                (r#".["tab\u0009key"]"#, "2"),
+                (r#"{"x\\y": .["a\"b"]}"#, r#"{"x\\y":1}"#),
+                (r#""\u00e9\n""#, r#""é\n""#),
+            ],
+        );
+    }
+
+    #[test]
+    fn pick() {
+        let v: Value = serde_json::from_str(r#"{"a":1,"b":{"c":2,"d":3}}"#).unwrap();
+        check(
+            &v,
+            &[
+                ("pick(.a, .b.c)", r#"{"a":1,"b":{"c":2}}"#),
+                ("pick(.b)", r#"{"b":{"c":2,"d":3}}"#),
+                ("pick(.x.y)", r#"{"x":{"y":null}}"#),
+                ("pick(.b[\"c\", \"d\"])", r#"{"b":{"c":2,"d":3}}"#),
+                ("pick(empty)", "null"),
+                ("pick(.)", r#"{"a":1,"b":{"c":2,"d":3}}"#),
+            ],
+        );
+
+        let v: Value = serde_json::from_str("[1, 2, 3, 4]").unwrap();
+        let q: Query = "pick(.[2])".parse().unwrap();
+        assert_eq!("[null,null,3]", q.execute(&v).unwrap()[0].to_string());
+        let q: Query = "pick(.[-1])".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+        let q: Query = "pick(.a + 1)".parse().unwrap();
+        assert!(q.execute(&v).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let v: Value =
+            serde_json::from_str(r#"{"a":{"k":{"x":1,"y":2},"n":1},"b":{"k":{"y":3,"z":4}}}"#)
+                .unwrap();
+        check(
+            &v,
+            &[
+                (".a * .b", r#"{"k":{"x":1,"y":3,"z":4},"n":1}"#),
+                (".a + .b", r#"{"k":{"y":3,"z":4},"n":1}"#),
+                (".a | merge(.k)", r#"{"k":{"x":1,"y":2},"n":1,"x":1,"y":2}"#),
+                (".a | merge(.)", r#"{"k":{"x":1,"y":2},"n":1}"#),
+            ],
+        );
+
+        let q: Query = "[.a | merge(.k, {\"n\": {\"m\": 2}})]".parse().unwrap();
+        assert_eq!(
+            r#"[{"k":{"x":1,"y":2},"n":1,"x":1,"y":2},{"k":{"x":1,"y":2},"n":{"m":2}}]"#,
+            q.execute(&v).unwrap()[0].to_string()
+        );
+
+        for query in &[
+            ".a * 1",
+            ".a * null",
+            "null * .a",
+            ".a | merge(.n)",
+            "1 | merge(.)",
+        ] {
+            let q: Query = query.parse().unwrap();
+            assert!(
+                matches!(q.execute(&v), Err(crate::QueryError::Operation(..))),
+                "{}",
+                query
+            );
+        }
+        let q: Query = ".a * null".parse().unwrap();
+        assert_eq!(
+            "Cannot multiply object ({\"k\":{\"x\":1,\"y\":2},\"n\":1}) and null (null)",
+            q.execute(&v).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn range() {
+        check(
+            &Value::Null,
+            &[
+                ("[range(5)]", "[0,1,2,3,4]"),
+                ("[range(2; 5)]", "[2,3,4]"),
+                ("[range(0; 10; 3)]", "[0,3,6,9]"),
+                ("[range(5; 0; -2)]", "[5,3,1]"),
+                ("[range(0; 1; 0.25)]", "[0.0,0.25,0.5,0.75]"),
+                ("[range(0; 3; 0)]", "[]"),
+                ("[range(-1)]", "[]"),
+                ("[range(2.5)]", "[0,1,2]"),
+                ("[range(0, 1; 3, 4)]", "[0,1,2,0,1,2,3,1,2,1,2,3]"),
+            ],
+        );
+
+        let q: Query = "range(\"a\")".parse().unwrap();
+        assert_eq!(
+            "string cannot be used as a range bound",
+            q.execute(&Value::Null).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn lazy_first() {
+        // Each of these would try to count to 1e18 if the range were computed in full
+        check(
+            &Value::Null,
+            &[
+                ("first(range(1e18))", "0"),
+                ("[limit(3; range(1e18))]", "[0,1,2]"),
+                ("nth(5; range(1e18))", "5"),
+                ("first(range(1e18) | . + 10)", "10"),
+                ("first(limit(1e18; range(1e18)))", "0"),
+                ("[limit(2; limit(1e18; range(1e18)))]", "[0,1]"),
+                ("0 | [limit(3; recurse(. + 1; true))]", "[0,1,2]"),
+                ("0 | nth(3; repeat(. + 1))", "3"),
+                ("[0] | first(repeat([.]) | recurse)", "[0]"),
+            ],
+        );
+    }
 }
@@ -1,50 +1,1190 @@
-use rq::query::{Executable, Query};
-use serde_json::Value;
+use rq::{
+    group_adjacent,
+    input::{DuplicateKeys, InputError, Inputs, RS},
+    query::{Executable, Query},
+    scope::{InputStream, Messages, Scope},
+    QueryError,
+};
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Serializer, Value};
 use std::{
-    env,
-    io::{self, Read},
+    env, fs,
+    io::{self, Read, Write},
+    iter, process,
 };
 
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    query: String,
+    exit_on_first_error: bool,
+    limit: Option<usize>,
+    seq: bool,
+    /// Wrap every output, across all input documents, into a single array.
+    collect: bool,
+    /// Path expression to navigate to before applying the query.
+    path: Option<String>,
+    duplicate_keys: DuplicateKeys,
+    no_trailing_newline: bool,
+    /// Print strings as their contents rather than as JSON.
+    raw_output: bool,
+    /// Print each output on a single line rather than pretty-printed.
+    compact_output: bool,
+    /// Indentation for pretty-printed output set by `--indent` or `--tab`, otherwise two spaces.
+    /// No indentation at all prints compactly.
+    indent: Option<String>,
+    /// Escape every non-ASCII character in the output as `\uXXXX`.
+    ascii_output: bool,
+    /// Print the keys of every object in the output in sorted order.
+    sort_keys: bool,
+    /// Run the query once against `null` instead of reading any input.
+    null_input: bool,
+    /// Run the query once against an array of all the input documents.
+    slurp: bool,
+    /// Set the exit code from the last output.
+    exit_status: bool,
+    /// Group consecutive inputs with the same result for this query into arrays.
+    group_by: Option<String>,
+    /// Variables bound by `--arg`, `--argjson` and `--rawfile`.
+    variables: Vec<(String, Value)>,
+    /// Files to read the input documents from in turn, instead of stdin.
+    files: Vec<String>,
+}
+
+impl Options {
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut query = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        // Flags split out of a combined short flag such as `-rn`, handled before the next argument
+        let mut combined = Vec::new();
+        while let Some(arg) = combined.pop().or_else(|| args.next()) {
+            match arg.as_str() {
+                "--exit-on-first-error" => options.exit_on_first_error = true,
+                "--seq" => options.seq = true,
+                "--collect" => options.collect = true,
+                "--no-trailing-newline" => options.no_trailing_newline = true,
+                "-r" | "--raw-output" => options.raw_output = true,
+                "-c" | "--compact-output" => options.compact_output = true,
+                "--tab" => options.indent = Some("\t".to_string()),
+                "--indent" => {
+                    let n = args.next().ok_or("--indent takes a number")?;
+                    match n.parse() {
+                        Ok(i) if i <= 7 => options.indent = Some(" ".repeat(i)),
+                        _ => return Err(format!("Invalid indent: {} (must be 0 to 7)", n)),
+                    }
+                }
+                "-a" | "--ascii-output" => options.ascii_output = true,
+                "-S" | "--sort-keys" => options.sort_keys = true,
+                "-n" | "--null-input" => options.null_input = true,
+                "-s" | "--slurp" => options.slurp = true,
+                "-e" | "--exit-status" => options.exit_status = true,
+                "--path" => options.path = Some(args.next().ok_or("--path takes a path")?),
+                "--group-by" => {
+                    options.group_by = Some(args.next().ok_or("--group-by takes a query")?)
+                }
+                "--arg" | "--argjson" => {
+                    let usage = || format!("{} takes a name and a value", arg);
+                    let name = args.next().ok_or_else(usage)?;
+                    let value = args.next().ok_or_else(usage)?;
+                    let value = if arg == "--arg" {
+                        Value::String(value)
+                    } else {
+                        serde_json::from_str(&value)
+                            .map_err(|e| format!("Invalid JSON for ${}: {}", name, e))?
+                    };
+                    options.variables.push((name, value));
+                }
+                "-f" | "--from-file" => {
+                    let path = args.next().ok_or_else(|| format!("{} takes a file", arg))?;
+                    let program = fs::read_to_string(&path)
+                        .map_err(|e| format!("Could not read {}: {}", path, e))?;
+                    query = Some(program);
+                }
+                "--rawfile" => {
+                    let usage = || "--rawfile takes a name and a file".to_string();
+                    let name = args.next().ok_or_else(usage)?;
+                    let path = args.next().ok_or_else(usage)?;
+                    let contents = fs::read_to_string(&path)
+                        .map_err(|e| format!("Could not read {}: {}", path, e))?;
+                    options.variables.push((name, Value::String(contents)));
+                }
+                "--limit" => {
+                    let n = args.next().ok_or("--limit takes a number")?;
+                    let n = n.parse().map_err(|_| format!("Invalid limit: {}", n))?;
+                    options.limit = Some(n);
+                }
+                "--dedupe-keys" => {
+                    let mode = args.next().ok_or("--dedupe-keys takes a mode")?;
+                    options.duplicate_keys = mode.parse()?;
+                }
+                _ if arg.starts_with("--dedupe-keys=") => {
+                    options.duplicate_keys = arg["--dedupe-keys=".len()..].parse()?;
+                }
+                _ if is_combined(&arg) => {
+                    for c in arg[1..].chars().rev() {
+                        let flag = long_flag(c)
+                            .ok_or_else(|| format!("Unknown option -{} in {}", c, arg))?;
+                        combined.push(flag.to_string());
+                    }
+                }
+                _ if is_option(&arg) => return Err(format!("Unknown option {}", arg)),
+                _ => positional.push(arg),
+            }
+        }
+        // Without a query file, the query is the first positional argument and the rest are
+        // input files
+        let mut positional = positional.into_iter();
+        options.query = query
+            .or_else(|| positional.next())
+            .ok_or("No query string provided")?;
+        options.files = positional.collect();
+        Ok(options)
+    }
+}
+
+/// Whether an argument is meant as an option. Queries can start with `-`, e.g. `-1` or `-.a`,
+/// but not with `--` or a letter straight after it.
+fn is_option(arg: &str) -> bool {
+    arg.starts_with("--")
+        || arg.starts_with('-') && arg[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+/// Whether an argument is several short flags written together, e.g. `-rn`.
+fn is_combined(arg: &str) -> bool {
+    arg.len() > 2 && arg.starts_with('-') && arg[1..].chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// The long form of each short flag that doesn't take a value, so that those can be combined.
+fn long_flag(c: char) -> Option<&'static str> {
+    match c {
+        'r' => Some("--raw-output"),
+        'c' => Some("--compact-output"),
+        'a' => Some("--ascii-output"),
+        'S' => Some("--sort-keys"),
+        'n' => Some("--null-input"),
+        's' => Some("--slurp"),
+        'e' => Some("--exit-status"),
+        _ => None,
+    }
+}
+
 fn main() {
-    let mut value_input = String::new();
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
-    if let Err(e) = handle.read_to_string(&mut value_input) {
-        eprintln!("Failed to read stdin: {:?}", e.kind());
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
     };
 
-    let value: Value = match serde_json::from_str(&value_input) {
-        Ok(v) => v,
+    let input = if options.files.is_empty() {
+        Input::from(io::stdin().lock())
+    } else {
+        match open(&options.files) {
+            Ok(i) => i,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+    };
+    let stdout = io::stdout();
+    let stderr = io::stderr();
+    let code = run(&options, input, &mut stdout.lock(), &mut stderr.lock());
+    process::exit(code);
+}
+
+/// Where the input documents are read from.
+enum Input {
+    Stream(Box<dyn Read>),
+    /// Each file is parsed on its own, so an unterminated document at the end of one can't
+    /// run into the start of the next.
+    Files(Vec<(String, fs::File)>),
+}
+
+impl<R: Read + 'static> From<R> for Input {
+    fn from(reader: R) -> Self {
+        Input::Stream(Box::new(reader))
+    }
+}
+
+/// Opens every file up front, so that a missing one is reported before any input is read.
+fn open(files: &[String]) -> Result<Input, String> {
+    files
+        .iter()
+        .map(|path| {
+            fs::File::open(path)
+                .map(|file| (path.clone(), file))
+                .map_err(|e| format!("Could not open {}: {}", path, e))
+        })
+        .collect::<Result<_, _>>()
+        .map(Input::Files)
+}
+
+fn run<O: Write, E: Write>(
+    options: &Options,
+    input: impl Into<Input>,
+    out: &mut O,
+    err: &mut E,
+) -> i32 {
+    let query: Query = match options.query.parse() {
+        Ok(q) => q,
         Err(e) => {
-            return eprintln!(
-                "Failed to parse document: {:?} at line {} column {}",
-                e.classify(),
-                e.line(),
-                e.column()
-            )
+            let _ = writeln!(err, "Failed to parse query string: {}", e);
+            if let Some(caret) = e.caret() {
+                let _ = writeln!(err, "{}", caret);
+            }
+            return 3;
         }
     };
 
-    let query_input = match env::args().nth(1) {
-        Some(q) => q,
-        None => return eprintln!("No query string provided"),
+    let path = match &options.path {
+        Some(p) => match p.parse::<Query>() {
+            Ok(q) if q.is_path_expression() => {
+                let optional = is_optional(&q);
+                Some((q, optional))
+            }
+            Ok(_) => {
+                let _ = writeln!(err, "Not a path expression: {}", p);
+                return 3;
+            }
+            Err(e) => {
+                let _ = writeln!(err, "Failed to parse path: {}", e);
+                if let Some(caret) = e.caret() {
+                    let _ = writeln!(err, "{}", caret);
+                }
+                return 3;
+            }
+        },
+        None => None,
     };
 
-    let query: Query = match query_input.parse() {
-        Ok(q) => q,
-        Err(e) => return eprintln!("Failed to parse query string: {}", e),
+    let group_by = match &options.group_by {
+        Some(g) => match g.parse::<Query>() {
+            Ok(q) => Some(q),
+            Err(e) => {
+                let _ = writeln!(err, "Failed to parse group key: {}", e);
+                if let Some(caret) = e.caret() {
+                    let _ = writeln!(err, "{}", caret);
+                }
+                return 3;
+            }
+        },
+        None => None,
     };
 
-    let results = match query.execute(&value) {
-        Ok(r) => r,
-        Err(e) => return eprintln!("Failed to execute query: {}", e),
+    let (seq, duplicate_keys) = (options.seq, options.duplicate_keys);
+    let read = move |reader| {
+        Inputs::new(reader)
+            .with_seq(seq)
+            .with_duplicate_keys(duplicate_keys)
+    };
+    let inputs: Box<dyn Iterator<Item = Result<Value, InputError>>> = match input.into() {
+        Input::Stream(reader) => Box::new(read(reader)),
+        Input::Files(files) => Box::new(files.into_iter().flat_map(move |(path, file)| {
+            let reader: Box<dyn Read> = Box::new(file);
+            read(reader).map(move |d| d.map_err(|e| InputError::File(path.clone(), Box::new(e))))
+        })),
     };
+    let variables = Scope::new().with_variables(options.variables.clone());
+    // The documents the query runs against come from the same stream that `input` and `inputs`
+    // read from, so each document is only seen once
+    let stream = match group_by {
+        Some(key) => InputStream::new(group_adjacent(inputs, key, variables.clone())),
+        None => InputStream::new(inputs.map(|d| d.map_err(QueryError::from))),
+    };
+    let messages = Messages::new();
+    let scope = variables
+        .with_inputs(stream.clone())
+        .with_messages(messages.clone());
+    let mut printer = Printer::new(out, options);
+    let mut code = 0;
+    let mut emitted = 0;
+    let mut last = None;
+    let mut collected = Vec::new();
+    let mut documents: Box<dyn Iterator<Item = Result<Value, QueryError>>> = if options.null_input {
+        Box::new(iter::once(Ok(Value::Null)))
+    } else if options.slurp {
+        let slurped = stream.collect::<Result<Vec<_>, _>>().map(Value::Array);
+        Box::new(iter::once(slurped))
+    } else {
+        Box::new(stream)
+    };
+
+    // Once the limit is reached, stop without reading any further input
+    while options.limit != Some(emitted) {
+        let document = match documents.next() {
+            Some(d) => d,
+            None => break,
+        };
+        let value = match document {
+            Ok(v) => v,
+            Err(QueryError::Input(e)) => {
+                let _ = writeln!(err, "{}", e);
+                code = 2;
+                if options.exit_on_first_error {
+                    break;
+                }
+                continue;
+            }
+            Err(e) => {
+                let _ = writeln!(err, "Failed to group input: {}", e);
+                code = 5;
+                continue;
+            }
+        };
+
+        let targets = match &path {
+            Some((p, optional)) => match navigate(p, *optional, &value, &scope) {
+                Ok(t) => t,
+                Err(e) => {
+                    let _ = writeln!(err, "{}", e);
+                    code = 5;
+                    continue;
+                }
+            },
+            None => vec![value],
+        };
+
+        // Results are printed as they're produced, so that a limit or an error stops the query
+        // from running any further
+        let mut produced = false;
+        let mut failed = false;
+        let results = targets
+            .into_iter()
+            .flat_map(|t| query.execute_iter_in(t, scope.clone()));
+        for result in results {
+            // Messages from `debug` and `stderr` come before the results they were written during
+            let _ = err.write_all(&messages.take());
+            let result = match result {
+                Ok(r) => r,
+                Err(e) => {
+                    let _ = writeln!(err, "Failed to execute query: {}", e);
+                    code = 5;
+                    failed = true;
+                    break;
+                }
+            };
+            produced = true;
+
+            if options.collect {
+                collected.push(result);
+            } else if printer.value(&result).is_err() {
+                // Nothing more can be written, e.g. the downstream end of a pipe was closed
+                return code;
+            } else {
+                last = Some(result);
+            }
+
+            emitted += 1;
+            if options.limit == Some(emitted) {
+                break;
+            }
+        }
+        let _ = err.write_all(&messages.take());
+
+        if !produced && !failed && !options.collect {
+            let _ = printer.line("No results");
+        }
+    }
+
+    if options.collect {
+        let collected = Value::Array(collected);
+        let _ = printer.value(&collected);
+        last = Some(collected);
+    }
+
+    // Errors take precedence over the exit status of the last output
+    match last {
+        _ if !options.exit_status || code != 0 => code,
+        None => 4,
+        Some(Value::Null) | Some(Value::Bool(false)) => 1,
+        Some(_) => 0,
+    }
+}
+
+/// Finds the values at `path`, where a missing value is an error unless the path is optional.
+fn navigate(
+    path: &Query,
+    optional: bool,
+    value: &Value,
+    scope: &Scope,
+) -> Result<Vec<Value>, String> {
+    let mut targets = Vec::new();
+    for target in path
+        .locate(value, scope)
+        .map_err(|e| format!("Failed to navigate path: {}", e))?
+    {
+        match target {
+            Some(t) => targets.push(t),
+            None if optional => {}
+            None => return Err("Path does not exist in input".to_string()),
+        }
+    }
+    Ok(targets)
+}
+
+/// Whether a path ends in `?`, making it produce no input rather than an error when missing.
+fn is_optional(path: &Query) -> bool {
+    match path {
+        Query::Optional(_) => true,
+        Query::Chain(chain) => is_optional(&chain.1),
+        Query::Split(split) => is_optional(&split.0) && is_optional(&split.1),
+        _ => false,
+    }
+}
+
+/// Writes each output on its own line. Without a trailing newline, the line break is held
+/// back until the next output so that nothing follows the last one.
+///
+/// Every line is flushed as it's written so that a downstream reader sees results promptly
+/// when the input arrives incrementally, e.g. from `tail -f`.
+struct Printer<'a, O: Write> {
+    out: &'a mut O,
+    options: &'a Options,
+    pending_newline: bool,
+}
+
+impl<'a, O: Write> Printer<'a, O> {
+    fn new(out: &'a mut O, options: &'a Options) -> Self {
+        Printer {
+            out,
+            options,
+            pending_newline: false,
+        }
+    }
+
+    // The output is serialized, then ASCII-escaped, then framed for a JSON text sequence
+    fn value(&mut self, value: &Value) -> io::Result<()> {
+        let sorted;
+        let value = if self.options.sort_keys {
+            sorted = sort_keys(value);
+            &sorted
+        } else {
+            value
+        };
+        let mut json = match value {
+            Value::String(s) if self.options.raw_output => s.clone(),
+            vv if self.options.compact_output || self.options.indent.as_deref() == Some("") => {
+                serde_json::to_string(vv)?
+            }
+            vv => pretty(vv, self.options.indent.as_deref().unwrap_or("  "))?,
+        };
+        if self.options.ascii_output {
+            json = ascii_escape(&json);
+        }
+        if self.options.seq {
+            self.line(&format!("{}{}", RS as char, json))
+        } else {
+            self.line(&json)
+        }
+    }
+
+    fn line(&mut self, line: &str) -> io::Result<()> {
+        if self.pending_newline {
+            writeln!(self.out)?;
+        }
+        write!(self.out, "{}", line)?;
+        if self.options.no_trailing_newline {
+            self.pending_newline = true;
+        } else {
+            writeln!(self.out)?;
+        }
+        self.out.flush()
+    }
+}
+
+// The order of a map's keys depends on how serde_json was built, so they're sorted explicitly
+fn sort_keys(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(k, _)| *k);
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), sort_keys(v)))
+                    .collect(),
+            )
+        }
+        Value::Array(arr) => Value::Array(arr.iter().map(sort_keys).collect()),
+        vv => vv.clone(),
+    }
+}
+
+fn pretty(value: &Value, indent: &str) -> io::Result<String> {
+    let mut json = Vec::new();
+    let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+    value.serialize(&mut Serializer::with_formatter(&mut json, formatter))?;
+    // Serializing only ever writes valid UTF-8
+    Ok(String::from_utf8(json).unwrap_or_default())
+}
+
+// Characters outside the BMP are escaped as a UTF-16 surrogate pair, as JSON requires
+fn ascii_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii() {
+            res.push(c);
+        } else {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                res.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        Options::parse(args.iter().map(|s| s.to_string())).unwrap()
+    }
+
+    fn run_str(options: &Options, input: &str) -> (i32, String, String) {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let input = io::Cursor::new(input.to_string().into_bytes());
+        let code = run(options, input, &mut out, &mut err);
+        (
+            code,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn parse_options() {
+        assert!(Options::parse(vec![]).is_err());
+        assert!(Options::parse(vec!["--limit".to_string()]).is_err());
+        assert!(Options::parse(vec!["--limit".to_string(), "x".to_string()]).is_err());
+
+        assert_eq!(
+            Options {
+                query: ".foo".to_string(),
+                exit_on_first_error: true,
+                limit: Some(3),
+                seq: false,
+                collect: false,
+                path: None,
+                duplicate_keys: DuplicateKeys::Last,
+                no_trailing_newline: false,
+                raw_output: false,
+                compact_output: false,
+                indent: None,
+                ascii_output: false,
+                sort_keys: false,
+                null_input: false,
+                slurp: false,
+                exit_status: false,
+                group_by: None,
+                variables: Vec::new(),
+                files: Vec::new(),
+            },
+            options(&["--exit-on-first-error", "--limit", "3", ".foo"])
+        );
+        assert_eq!(
+            vec!["a.json".to_string(), "b.json".to_string()],
+            options(&[".", "a.json", "b.json"]).files
+        );
+
+        // Short flags can be combined, but unknown options aren't taken as the query
+        assert_eq!(options(&["-r", "-n", "-c", "."]), options(&["-rnc", "."]));
+        assert_eq!(options(&["-S", "-e", "."]), options(&["-Se", "."]));
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        assert_eq!(
+            Err("Unknown option -x in -rx".to_string()),
+            parse(&["-rx", "."])
+        );
+        assert_eq!(Err("Unknown option -q".to_string()), parse(&["-q", "."]));
+        assert_eq!(
+            Err("Unknown option --raw".to_string()),
+            parse(&["--raw", "."])
+        );
+        assert_eq!("-1", options(&["-1"]).query);
+        assert_eq!("-.a", options(&["-n", "-.a"]).query);
+        let (_, out, _) = run_str(&options(&["-rn", r#"" ""#]), "");
+        assert_eq!(" \n", out);
+    }
+
+    #[test]
+    fn multiple_documents() {
+        let (code, out, _) = run_str(&options(&[".+1"]), "1 2 3");
+        assert_eq!(0, code);
+        assert_eq!("2\n3\n4\n", out);
+
+        let (_, out, _) = run_str(&options(&[".a"]), r#"{"a":1}{"a":2}"#);
+        assert_eq!("1\n2\n", out);
+    }
+
+    #[test]
+    fn malformed_document() {
+        let input = r#"{"a":1} not-json {"b":2}"#;
+
+        let (code, out, err) = run_str(&options(&[".[]"]), input);
+        assert_eq!(2, code);
+        assert_eq!("1\n2\n", out);
+        assert!(err.contains("at byte 8"));
+
+        let (code, out, err) = run_str(&options(&["--exit-on-first-error", ".[]"]), input);
+        assert_eq!(2, code);
+        assert_eq!("1\n", out);
+        assert!(err.contains("at byte 8"));
+    }
+
+    struct Unreadable;
+
+    impl Read for Unreadable {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            panic!("Input was read past the limit");
+        }
+    }
+
+    #[test]
+    fn limit() {
+        let (code, out, _) = run_str(&options(&["--limit", "3", ".[]"]), "[1,2,3,4,5]");
+        assert_eq!(0, code);
+        assert_eq!("1\n2\n3\n", out);
+
+        let (_, out, _) = run_str(&options(&["--limit", "3", ".[]"]), "[1,2] [3,4] [5]");
+        assert_eq!("1\n2\n3\n", out);
+
+        let (_, out, _) = run_str(&options(&["--limit", "0", "."]), "1");
+        assert_eq!("", out);
+
+        let input = "[1,2,3,4,5]".as_bytes().chain(Unreadable);
+        let mut out = Vec::new();
+        let code = run(
+            &options(&["--limit", "3", ".[]"]),
+            input,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(0, code);
+        assert_eq!("1\n2\n3\n", String::from_utf8(out).unwrap());
+
+        // The query itself stops at the limit, even when it would go on reading or counting
+        let input = "1 2 3".as_bytes().chain(Unreadable);
+        let mut out = Vec::new();
+        let code = run(
+            &options(&["-n", "--limit", "2", "inputs"]),
+            input,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(0, code);
+        assert_eq!("1\n2\n", String::from_utf8(out).unwrap());
+
+        let (code, out, _) = run_str(&options(&["-n", "--limit", "2", "range(1e18)"]), "");
+        assert_eq!(0, code);
+        assert_eq!("0\n1\n", out);
+    }
 
-    if results.is_empty() {
-        println!("No results")
+    #[test]
+    fn streamed_results() {
+        let (code, out, err) = run_str(&options(&["range(1e300) | error"]), "1");
+        assert_eq!(5, code);
+        assert_eq!("", out);
+        assert!(err.contains("Failed to execute query"));
+
+        let (code, out, _) = run_str(&options(&[".[] | 1 / ."]), "[1, 0, 2] [4]");
+        assert_eq!(5, code);
+        assert_eq!("1\n0.25\n", out);
+    }
+
+    #[test]
+    fn path() {
+        let input = r#"{"data": [{"id": 1}, {"id": 2}]}"#;
+        let (code, out, _) = run_str(&options(&["--path", ".data", ".[] | .id"]), input);
+        assert_eq!(0, code);
+        assert_eq!("1\n2\n", out);
+
+        let (code, out, _) = run_str(&options(&["--path", ".data[]", ".id"]), input);
+        assert_eq!(0, code);
+        assert_eq!("1\n2\n", out);
+
+        let (code, _, err) = run_str(&options(&["--path", ".missing", "."]), input);
+        assert_eq!(5, code);
+        assert!(err.contains("does not exist"));
+
+        let (code, out, _) = run_str(&options(&["--path", ".missing?", "."]), input);
+        assert_eq!(0, code);
+        assert_eq!("No results\n", out);
+
+        // An existing key holding `null` is there to be navigated to
+        let (code, out, _) = run_str(&options(&["--path", ".a", "."]), r#"{"a": null}"#);
+        assert_eq!(0, code);
+        assert_eq!("null\n", out);
+
+        let (code, out, _) = run_str(&options(&["--path", ".a.b? ", "."]), r#"{"a": {}}"#);
+        assert_eq!(0, code);
+        assert_eq!("No results\n", out);
+
+        let (code, _, _) = run_str(&options(&["--path", ".a?.b", "."]), r#"{"a": {}}"#);
+        assert_eq!(5, code);
+
+        let (code, _, _) = run_str(&options(&["--path", ".data + 1", "."]), input);
+        assert_eq!(3, code);
+    }
+
+    #[test]
+    fn files() {
+        let dir = env::temp_dir().join(format!("rq-files-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.json");
+        let b = dir.join("b.json");
+        fs::write(&a, r#"{"n": 1}"#).unwrap();
+        fs::write(&b, "2").unwrap();
+        let files = vec![a.display().to_string(), b.display().to_string()];
+
+        let mut out = Vec::new();
+        let code = run(
+            &options(&["-c", "."]),
+            open(&files).unwrap(),
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(0, code);
+        assert_eq!("{\"n\":1}\n2\n", String::from_utf8(out).unwrap());
+
+        // An unterminated document is reported against its own file, without consuming the next
+        fs::write(&a, r#"{"n": [1"#).unwrap();
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let code = run(
+            &options(&["-c", "."]),
+            open(&files).unwrap(),
+            &mut out,
+            &mut err,
+        );
+        assert_eq!(2, code);
+        assert_eq!("2\n", String::from_utf8(out).unwrap());
+        let err = String::from_utf8(err).unwrap();
+        assert!(err.starts_with(&format!("{}: Failed to parse document at byte 0", files[0])));
+
+        let args = ["--rawfile", "text", &files[1], "-n", "$text"];
+        let (_, out, _) = run_str(&options(&args), "");
+        assert_eq!("\"2\"\n", out);
+
+        let program = dir.join("program.jq");
+        fs::write(
+            &program,
+            "# the number, if there is one\n.n?\n| . + 1 # increment\n",
+        )
+        .unwrap();
+        let inline = run_str(&options(&[".n? | . + 1"]), r#"{"n": 1} {"n": 2}"#);
+        let args = ["-f", &program.display().to_string()];
+        assert_eq!(inline, run_str(&options(&args), r#"{"n": 1} {"n": 2}"#));
+        assert_eq!(
+            files,
+            options(&[
+                &files[0],
+                "--from-file",
+                &program.display().to_string(),
+                &files[1]
+            ])
+            .files
+        );
+
+        let missing = dir.join("missing.json").display().to_string();
+        assert!(Options::parse(
+            ["--rawfile", "text", &missing, "."]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+        assert!(Options::parse(vec!["-f".to_string(), missing.clone()]).is_err());
+        assert!(open(&[missing]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dedupe_keys() {
+        let input = r#"{"a":1,"a":2}"#;
+        let (_, out, _) = run_str(&options(&[".a"]), input);
+        assert_eq!("2\n", out);
+
+        let (_, out, _) = run_str(&options(&["--dedupe-keys=first", ".a"]), input);
+        assert_eq!("1\n", out);
+
+        let (code, out, err) = run_str(&options(&["--dedupe-keys", "error", ".a"]), input);
+        assert_eq!(2, code);
+        assert_eq!("", out);
+        assert!(err.contains("duplicate key `a`"));
+
+        assert!(Options::parse(vec!["--dedupe-keys=all".to_string(), ".".to_string()]).is_err());
+    }
+
+    #[test]
+    fn no_trailing_newline() {
+        let (_, out, _) = run_str(&options(&["--no-trailing-newline", ".[]"]), "[1,2] [3]");
+        assert_eq!("1\n2\n3", out);
+
+        let (_, out, _) = run_str(&options(&["--no-trailing-newline", ".[]"]), "[]");
+        assert_eq!("No results", out);
+
+        let (_, out, _) = run_str(&options(&["--no-trailing-newline", "--collect", "."]), "1");
+        assert_eq!("[\n  1\n]", out);
+    }
+
+    #[test]
+    fn scalar_output() {
+        for scalar in &["null", "true", "false", "42", "3.14", "-1e-7", r#""hi""#] {
+            let (code, out, _) = run_str(&options(&["."]), scalar);
+            assert_eq!(0, code);
+            assert_eq!(format!("{}\n", scalar), out);
+        }
+
+        let (_, out, _) = run_str(&options(&["."]), r#""a\"b\n""#);
+        assert_eq!("\"a\\\"b\\n\"\n", out);
+    }
+
+    #[test]
+    fn raw_output() {
+        let (_, out, _) = run_str(&options(&["-r", "."]), r#""hi""#);
+        assert_eq!("hi\n", out);
+
+        let (_, out, _) = run_str(&options(&["--raw-output", "."]), r#""a\"b\n\tc""#);
+        assert_eq!("a\"b\n\tc\n", out);
+
+        for scalar in &["null", "true", "false", "42", "3.14"] {
+            let (_, out, _) = run_str(&options(&["-r", "."]), scalar);
+            assert_eq!(format!("{}\n", scalar), out);
+        }
+
+        let (_, out, _) = run_str(&options(&["-r", "."]), r#"["a"]"#);
+        assert_eq!("[\n  \"a\"\n]\n", out);
+    }
+
+    #[test]
+    fn null_input() {
+        let mut out = Vec::new();
+        let code = run(
+            &options(&["-n", "[., 1]"]),
+            Unreadable,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(0, code);
+        assert_eq!("[\n  null,\n  1\n]\n", String::from_utf8(out).unwrap());
+
+        let (_, out, _) = run_str(&options(&["--null-input", "."]), "1 2");
+        assert_eq!("null\n", out);
+    }
+
+    #[test]
+    fn variables() {
+        let (code, out, _) = run_str(
+            &options(&["--arg", "who", "world", "-n", r#""hello \($who)""#]),
+            "",
+        );
+        assert_eq!(0, code);
+        assert_eq!("\"hello world\"\n", out);
+
+        let (_, out, _) = run_str(
+            &options(&[
+                "--argjson",
+                "n",
+                r#"{"a":[1]}"#,
+                "--arg",
+                "n2",
+                "2",
+                "[$n.a[0], $n2]",
+            ]),
+            "null",
+        );
+        assert_eq!("[\n  1,\n  \"2\"\n]\n", out);
+
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        assert!(parse(&["--arg", "x"]).is_err());
+        assert!(parse(&["--argjson", "x", "{", "."]).is_err());
+    }
+
+    #[test]
+    fn slurp() {
+        let (code, out, _) = run_str(&options(&["-s", "."]), "1\n[2]\n{\"a\":3}\n");
+        assert_eq!(0, code);
+        assert_eq!(
+            "[\n  1,\n  [\n    2\n  ],\n  {\n    \"a\": 3\n  }\n]\n",
+            out
+        );
+
+        let (_, out, _) = run_str(&options(&["--slurp", "."]), "");
+        assert_eq!("[]\n", out);
+
+        let (code, out, err) = run_str(&options(&["-s", "."]), "1 x 2");
+        assert_eq!(2, code);
+        assert_eq!("", out);
+        assert!(err.contains("at byte 2"));
+    }
+
+    #[test]
+    fn exit_status() {
+        for (query, input, expected) in &[
+            (".", "1", 0),
+            (".", "null", 1),
+            (".[]", "[1, false]", 1),
+            (".[]", "[false, 0]", 0),
+            ("empty", "1", 4),
+            (".[]", "[]", 4),
+            (".a", "1", 5),
+        ] {
+            let (code, _, _) = run_str(&options(&["-e", query]), input);
+            assert_eq!(*expected, code, "{} on {}", query, input);
+        }
+
+        let (code, _, _) = run_str(&options(&["--exit-status", "--collect", "empty"]), "1");
+        assert_eq!(0, code);
+        let (code, _, _) = run_str(&options(&["."]), "null");
+        assert_eq!(0, code);
+    }
+
+    /// Only exposes what has been flushed, which is checked before each further read of input.
+    struct Flushed {
+        buffered: Vec<u8>,
+        flushed: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Write for Flushed {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffered.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed.borrow_mut().append(&mut self.buffered);
+            Ok(())
+        }
+    }
+
+    struct Incremental {
+        chunks: Vec<(&'static str, &'static str)>,
+        flushed: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for Incremental {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.chunks.is_empty() {
+                return Ok(0);
+            }
+            let (expected, chunk) = self.chunks.remove(0);
+            assert_eq!(expected, String::from_utf8_lossy(&self.flushed.borrow()));
+            buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn flush() {
+        let flushed = Rc::new(RefCell::new(Vec::new()));
+        let input = Incremental {
+            chunks: vec![("", "1\n"), ("1\n", "2\n"), ("1\n2\n", "3")],
+            flushed: flushed.clone(),
+        };
+        let mut out = Flushed {
+            buffered: Vec::new(),
+            flushed: flushed.clone(),
+        };
+        run(&options(&["."]), input, &mut out, &mut Vec::new());
+        assert_eq!("1\n2\n3\n", String::from_utf8_lossy(&flushed.borrow()));
+
+        let flushed = Rc::new(RefCell::new(Vec::new()));
+        let input = Incremental {
+            chunks: vec![("", "1\n"), ("1", "2\n")],
+            flushed: flushed.clone(),
+        };
+        let mut out = Flushed {
+            buffered: Vec::new(),
+            flushed: flushed.clone(),
+        };
+        run(
+            &options(&["--no-trailing-newline", "."]),
+            input,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!("1\n2", String::from_utf8_lossy(&flushed.borrow()));
+    }
+
+    #[test]
+    fn json_sequence() {
+        let input = "\x1e{\"a\":1}\n\x1e{\"a\":2}\n";
+        let (code, out, _) = run_str(&options(&["--seq", ".a"]), input);
+        assert_eq!(0, code);
+        assert_eq!("\x1e1\n\x1e2\n", out);
+    }
+
+    #[test]
+    fn collect() {
+        let (code, out, _) = run_str(&options(&["--collect", ".[]"]), "[1,2] [3]");
+        assert_eq!(0, code);
+        assert_eq!("[\n  1,\n  2,\n  3\n]\n", out);
+
+        let (_, out, _) = run_str(&options(&["--collect", "--limit", "2", ".[]"]), "[1,2,3]");
+        assert_eq!("[\n  1,\n  2\n]\n", out);
+
+        let (_, out, _) = run_str(&options(&["--collect", "empty"]), "1 2");
+        assert_eq!("[]\n", out);
+    }
+
+    #[test]
+    fn compact_output() {
+        let (code, out, _) = run_str(&options(&["-c", "."]), r#"{"a": [1, {"b": 2}]}"#);
+        assert_eq!(0, code);
+        assert_eq!("{\"a\":[1,{\"b\":2}]}\n", out);
+    }
+
+    #[test]
+    fn indent() {
+        let input = r#"{"a": [1]}"#;
+        let (_, out, _) = run_str(&options(&["."]), input);
+        assert_eq!("{\n  \"a\": [\n    1\n  ]\n}\n", out);
+
+        let (_, out, _) = run_str(&options(&["--indent", "4", "."]), input);
+        assert_eq!("{\n    \"a\": [\n        1\n    ]\n}\n", out);
+
+        let (_, out, _) = run_str(&options(&["--tab", "."]), input);
+        assert_eq!("{\n\t\"a\": [\n\t\t1\n\t]\n}\n", out);
+
+        let (_, out, _) = run_str(&options(&["--indent", "0", "."]), input);
+        assert_eq!("{\"a\":[1]}\n", out);
+
+        assert!(Options::parse(vec!["--indent".to_string(), "8".to_string()]).is_err());
+        assert!(Options::parse(vec!["--indent".to_string(), "x".to_string()]).is_err());
+    }
+
+    #[test]
+    fn sort_keys() {
+        // Without serde_json's `preserve_order` feature objects always print in key order, so
+        // these pass without the flag too. They document its contract in case that changes.
+        let (_, out, _) = run_str(&options(&["-S", "-c", "."]), r#"{"b":1,"a":2}"#);
+        assert_eq!("{\"a\":2,\"b\":1}\n", out);
+
+        let input = r#"[{"d":{"z":1,"y":2},"c":[{"x":3,"w":4}]}]"#;
+        let (_, out, _) = run_str(&options(&["--sort-keys", "-c", "."]), input);
+        assert_eq!(
+            "[{\"c\":[{\"w\":4,\"x\":3}],\"d\":{\"y\":2,\"z\":1}}]\n",
+            out
+        );
+    }
+
+    #[test]
+    fn ascii_output() {
+        let (_, out, _) = run_str(&options(&["-a", "."]), r#""é😀""#);
+        assert_eq!("\"\\u00e9\\ud83d\\ude00\"\n", out);
+
+        let (_, out, _) = run_str(&options(&["-a", "-r", "."]), r#""aé""#);
+        assert_eq!("a\\u00e9\n", out);
+    }
+
+    #[test]
+    fn compact_ascii_sequence() {
+        let input = "\x1e{\"name\":\"café\",\"n\":[1,2]}\n";
+        let (code, out, _) = run_str(&options(&["-c", "-a", "--seq", "."]), input);
+        assert_eq!(0, code);
+        assert_eq!("\x1e{\"n\":[1,2],\"name\":\"caf\\u00e9\"}\n", out);
+    }
+
+    #[test]
+    fn query_parse_error() {
+        let (code, out, err) = run_str(&options(&[".a | | .b"]), "1");
+        assert_eq!(3, code);
+        assert_eq!("", out);
+        assert_eq!(
+            "Failed to parse query string: Invalid syntax at offset 3 (Eof)\n.a | | .b\n   ^\n",
+            err
+        );
+    }
+
+    #[test]
+    fn group_by() {
+        let input = r#"{"host":"a","n":1} {"host":"a","n":2} {"host":"b","n":3} {"host":"c","n":4} {"host":"c","n":5}"#;
+        let (code, out, _) = run_str(
+            &options(&["-c", "--group-by", ".host", "[.[] | .n]"]),
+            input,
+        );
+        assert_eq!(0, code);
+        assert_eq!("[1,2]\n[3]\n[4,5]\n", out);
+
+        let (code, out, err) = run_str(
+            &options(&["-c", "--group-by", ".a", "."]),
+            "{\"a\":1} 2 {\"a\":1}",
+        );
+        assert_eq!(5, code);
+        // The document without a key is skipped, like a malformed one
+        assert_eq!("[{\"a\":1},{\"a\":1}]\n", out);
+        assert!(err.contains("Failed to group input"));
+
+        let (code, _, _) = run_str(&options(&["--group-by", ".a |", "."]), "1");
+        assert_eq!(3, code);
+    }
+
+    #[test]
+    fn inputs() {
+        let (code, out, _) = run_str(&options(&["-n", "-c", "[inputs]"]), "1 2 3");
+        assert_eq!(0, code);
+        assert_eq!("[1,2,3]\n", out);
+
+        let (code, out, _) = run_str(&options(&["-c", "[., input]"]), "1 2 3 4");
+        assert_eq!(0, code);
+        assert_eq!("[1,2]\n[3,4]\n", out);
+
+        let (code, out, _) = run_str(
+            &options(&["-n", "reduce inputs as $x (0; . + $x)"]),
+            "1 2 3",
+        );
+        assert_eq!(0, code);
+        assert_eq!("6\n", out);
+
+        let (code, _, err) = run_str(&options(&["[., input]"]), "1 2 3");
+        assert_eq!(5, code);
+        assert!(err.contains("No more inputs"));
+
+        let input = "1 2 ".as_bytes().chain(Unreadable);
+        let mut out = Vec::new();
+        let code = run(
+            &options(&["-n", "-c", "[limit(2; inputs)]"]),
+            input,
+            &mut out,
+            &mut Vec::new(),
+        );
+        assert_eq!(0, code);
+        assert_eq!("[1,2]\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn debug_messages() {
+        let (code, out, err) = run_str(&options(&["debug | .+1"]), "1");
+        assert_eq!(0, code);
+        assert_eq!("2\n", out);
+        assert_eq!("[\"DEBUG:\",1]\n", err);
+
+        let (code, out, err) = run_str(&options(&["-c", "stderr | [.]"]), r#"{"a":"b"} 2"#);
+        assert_eq!(0, code);
+        assert_eq!("[{\"a\":\"b\"}]\n[2]\n", out);
+        assert_eq!("{\"a\":\"b\"}2", err);
+
+        let (code, _, err) = run_str(&options(&["debug | error"]), "\"x\"");
+        assert_eq!(5, code);
+        assert!(err.starts_with("[\"DEBUG:\",\"x\"]\nFailed to execute query"));
+    }
+    #[test]
+    fn stream_round_trip() {
+        let (code, out, _) = run_str(&options(&["-c", "fromstream(tostream)"]), r#"{"a":[1,2]}"#);
+        assert_eq!(0, code);
+        assert_eq!("{\"a\":[1,2]}\n", out);
     }
-    for result in results {
-        let pretty = serde_json::to_string_pretty(&result).unwrap();
-        println!("{}", pretty);
+    #[test]
+    fn dates() {
+        let (code, out, _) = run_str(&options(&["todate"]), "0");
+        assert_eq!(0, code);
+        assert_eq!("\"1970-01-01T00:00:00Z\"\n", out);
     }
 }
@@ -0,0 +1,149 @@
+use regex::{Captures, Match, Regex, RegexBuilder};
+use serde_json::Value;
+
+use crate::{type_str, QueryError};
+
+/// A regex along with the jq flags that change how it's applied, rather than what it matches.
+pub(crate) struct Pattern {
+    regex: Regex,
+    global: bool,
+    ignore_empty: bool,
+}
+
+impl Pattern {
+    /// Compiles a regex with jq's flags, where `null` means no flags.
+    pub fn new(re: &Value, flags: &Value) -> Result<Self, QueryError> {
+        let re = match re {
+            Value::String(s) => s,
+            vv => return Err(QueryError::Unsupported(type_str(vv), "used as a regex")),
+        };
+        let flags = match flags {
+            Value::String(s) => s.as_str(),
+            Value::Null => "",
+            vv => return Err(QueryError::Unsupported(type_str(vv), "used as regex flags")),
+        };
+
+        let mut builder = RegexBuilder::new(re);
+        let (mut global, mut ignore_empty) = (false, false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'n' => ignore_empty = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                'x' => {
+                    builder.ignore_whitespace(true);
+                }
+                's' => {
+                    builder.dot_matches_new_line(true);
+                }
+                _ => return Err(QueryError::RegexFlags(flags.to_string())),
+            }
+        }
+        let regex = builder
+            .build()
+            .map_err(|e| QueryError::Regex(re.to_string(), e.to_string()))?;
+        Ok(Pattern {
+            regex,
+            global,
+            ignore_empty,
+        })
+    }
+
+    /// The matches in `s`, which is only the first one unless the pattern is global.
+    pub fn matches<'a>(&'a self, s: &'a str) -> impl Iterator<Item = Match<'a>> + 'a {
+        self.captures(s).map(|c| c.get(0).unwrap())
+    }
+
+    /// The capture groups of each match in `s`, which is only the first one unless the pattern
+    /// is global.
+    pub fn captures<'a>(&'a self, s: &'a str) -> impl Iterator<Item = Captures<'a>> + 'a {
+        self.all_captures(s)
+            .take(if self.global { usize::MAX } else { 1 })
+    }
+
+    fn all_captures<'a>(&'a self, s: &'a str) -> impl Iterator<Item = Captures<'a>> + 'a {
+        let ignore_empty = self.ignore_empty;
+        self.regex
+            .captures_iter(s)
+            .filter(move |c| !(ignore_empty && c.get(0).unwrap().as_str().is_empty()))
+    }
+
+    /// Every match in `s` whether or not the pattern is global. Each is the matched text, or an
+    /// array of its groups when the pattern has any.
+    pub fn scan(&self, s: &str) -> Vec<Value> {
+        let text =
+            |m: Option<Match>| m.map_or(Value::Null, |m| Value::String(m.as_str().to_string()));
+        self.all_captures(s)
+            .map(|c| {
+                if c.len() > 1 {
+                    Value::Array(c.iter().skip(1).map(text).collect())
+                } else {
+                    text(c.get(0))
+                }
+            })
+            .collect()
+    }
+
+    /// An object of the named groups in a match, where a group that didn't take part is `null`.
+    pub fn named_groups(&self, captures: &Captures) -> Value {
+        Value::Object(
+            self.regex
+                .capture_names()
+                .flatten()
+                .map(|name| {
+                    let group = captures
+                        .name(name)
+                        .map_or(Value::Null, |m| Value::String(m.as_str().to_string()));
+                    (name.to_string(), group)
+                })
+                .collect(),
+        )
+    }
+
+    /// The pieces of `s` between every match, as splitting always applies the pattern globally.
+    pub fn split(&self, s: &str) -> Vec<Value> {
+        let mut pieces = Vec::new();
+        let mut last = 0;
+        for m in self.regex.find_iter(s) {
+            if self.ignore_empty && m.as_str().is_empty() {
+                continue;
+            }
+            pieces.push(Value::String(s[last..m.start()].to_string()));
+            last = m.end();
+        }
+        pieces.push(Value::String(s[last..].to_string()));
+        pieces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(re: &str, flags: &str) -> Result<Pattern, QueryError> {
+        Pattern::new(&Value::from(re), &Value::from(flags))
+    }
+
+    #[test]
+    fn flags() {
+        assert!(pattern("a", "gixsn").is_ok());
+        assert!(pattern("a", "q").is_err());
+        assert!(pattern("(", "").is_err());
+        assert!(Pattern::new(&Value::from(1), &Value::Null).is_err());
+        assert!(Pattern::new(&Value::from("a"), &Value::from(1)).is_err());
+
+        let p = pattern("a", "").unwrap();
+        assert_eq!(1, p.matches("aAa").count());
+        let p = pattern("a", "g").unwrap();
+        assert_eq!(2, p.matches("aAa").count());
+        let p = pattern("a", "gi").unwrap();
+        assert_eq!(3, p.matches("aAa").count());
+        let p = pattern("x*", "gn").unwrap();
+        assert_eq!(
+            vec!["xx"],
+            p.matches("axxb").map(|m| m.as_str()).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,14 +1,17 @@
-use std::iter::FromIterator;
+use std::{cmp::Ordering, fmt, iter::FromIterator};
 
 use crate::{
-    null,
-    parse::{parse_init, ParseError, Parseable},
+    compare::cmp_values,
+    describe, null,
+    parse::{keyword, parse_init, ParseError, Parseable},
     query::{iterate_results, Executable, Query},
-    single, space, type_str, QueryError, QueryResult,
+    scope::Scope,
+    single, space, truthy, QueryError, QueryResult,
 };
 use itertools::Itertools;
 use nom::{
     branch::alt,
+    bytes::complete::tag,
     character::complete::char,
     combinator::{opt, value},
     sequence::pair,
@@ -23,6 +26,35 @@ pub enum Sign {
     Mul,
     Div,
     Mod,
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl fmt::Display for Sign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Sign::Add => "+",
+            Sign::Sub => "-",
+            Sign::Mul => "*",
+            Sign::Div => "/",
+            Sign::Mod => "%",
+            Sign::And => "and",
+            Sign::Or => "or",
+            Sign::Eq => "==",
+            Sign::Ne => "!=",
+            Sign::Lt => "<",
+            Sign::Le => "<=",
+            Sign::Gt => ">",
+            Sign::Ge => ">=",
+        };
+        write!(f, "{}", s)
+    }
 }
 
 impl Parseable for Sign {
@@ -33,10 +65,25 @@ impl Parseable for Sign {
             value(Sign::Div, char('/')),
             value(Sign::Mod, char('%')),
             value(Sign::Mul, char('*')),
+            value(Sign::And, keyword("and")),
+            value(Sign::Or, keyword("or")),
+            parse_compare_sign,
         )))(input)
     }
 }
 
+// Longer signs come first so that `<=` isn't read as `<` followed by `=`
+fn parse_compare_sign(input: &str) -> IResult<&str, Sign, ParseError> {
+    alt((
+        value(Sign::Eq, tag("==")),
+        value(Sign::Ne, tag("!=")),
+        value(Sign::Le, tag("<=")),
+        value(Sign::Ge, tag(">=")),
+        value(Sign::Lt, char('<')),
+        value(Sign::Gt, char('>')),
+    ))(input)
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Op {
     pub left: Query,
@@ -44,10 +91,22 @@ pub struct Op {
     pub right: Query,
 }
 
+// Every operator is parsed right-associatively from operands of higher precedence, so a parsed
+// operation is written back the same way without needing any grouping
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.left, self.sign, self.right)
+    }
+}
+
 impl Executable for Op {
-    fn execute(&self, value: &Value) -> QueryResult {
-        let ls = self.left.execute(value)?;
-        let rs = self.right.execute(value)?;
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        if let Sign::And | Sign::Or = self.sign {
+            return self.execute_logical(value, scope);
+        }
+
+        let ls = self.left.execute_in(value, scope)?;
+        let rs = self.right.execute_in(value, scope)?;
 
         iterate_results(
             ls.into_iter()
@@ -57,19 +116,53 @@ impl Executable for Op {
     }
 }
 
-fn operate(sign: &Sign, l: &Value, r: &Value) -> QueryResult {
+impl Op {
+    // The right side is only evaluated when the left side doesn't decide the result
+    fn execute_logical(&self, value: &Value, scope: &Scope) -> QueryResult {
+        let mut res = Vec::new();
+        for l in self.left.execute_in(value, scope)? {
+            match (&self.sign, truthy(&l)) {
+                (Sign::And, false) => res.push(Value::Bool(false)),
+                (Sign::Or, true) => res.push(Value::Bool(true)),
+                _ => res.extend(
+                    self.right
+                        .execute_in(value, scope)?
+                        .iter()
+                        .map(|r| Value::Bool(truthy(r))),
+                ),
+            }
+        }
+        Ok(res)
+    }
+}
+
+pub(crate) fn operate(sign: &Sign, l: &Value, r: &Value) -> QueryResult {
     match sign {
         Sign::Add => add(l, r),
         Sign::Sub => sub(l, r),
         Sign::Mul => mul(l, r),
         Sign::Div => div(l, r),
         Sign::Mod => modulus(l, r),
+        Sign::And => single(Value::Bool(truthy(l) && truthy(r))),
+        Sign::Or => single(Value::Bool(truthy(l) || truthy(r))),
+        Sign::Eq => compare(l, r, |o| o == Ordering::Equal),
+        Sign::Ne => compare(l, r, |o| o != Ordering::Equal),
+        Sign::Lt => compare(l, r, |o| o == Ordering::Less),
+        Sign::Le => compare(l, r, |o| o != Ordering::Greater),
+        Sign::Gt => compare(l, r, |o| o == Ordering::Greater),
+        Sign::Ge => compare(l, r, |o| o != Ordering::Less),
     }
 }
 
+fn compare<F: Fn(Ordering) -> bool>(l: &Value, r: &Value, f: F) -> QueryResult {
+    single(Value::Bool(f(cmp_values(l, r))))
+}
+
 fn add(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => combine_numbers(n, m, |a, b| a + b, |a, b| a + b),
+        (Value::Number(n), Value::Number(m)) => {
+            combine_numbers(n, m, i64::checked_add, |a, b| a + b)
+        }
         (Value::String(s), Value::String(t)) => {
             single(Value::String(chain_collect(&s.chars(), &t.chars())))
         }
@@ -77,63 +170,80 @@ fn add(l: &Value, r: &Value) -> QueryResult {
         (Value::Object(o), Value::Object(p)) => single(Value::Object(chain_collect(o, p))),
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) | (Value::Null, v) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("add", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation("add", describe(v), describe(vv))),
     }
 }
 
 fn sub(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => combine_numbers(n, m, |a, b| a - b, |a, b| a - b),
+        (Value::Number(n), Value::Number(m)) => {
+            combine_numbers(n, m, i64::checked_sub, |a, b| a - b)
+        }
         (Value::Array(a), Value::Array(b)) => single(Value::Array(
             a.clone().into_iter().filter(|v| !b.contains(v)).collect(),
         )),
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("subtract", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation("subtract", describe(v), describe(vv))),
     }
 }
 
 fn mul(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => combine_numbers(n, m, |a, b| a * b, |a, b| a * b),
-        (Value::String(str), Value::Number(num)) => {
-            let i = num.as_u64().ok_or(QueryError::Numerical)? as usize;
-            if i == 0 {
-                null()
-            } else {
-                single(Value::String(
-                    std::iter::repeat(str.clone()).take(i).collect(),
-                ))
-            }
+        (Value::Number(n), Value::Number(m)) => {
+            combine_numbers(n, m, i64::checked_mul, |a, b| a * b)
         }
-        (Value::Object(o), Value::Object(p)) => single(multiply_objects(o, p)),
+        (Value::String(str), Value::Number(num)) => repeat(str, num),
+        (Value::Object(_), _) | (_, Value::Object(_)) => single(merge(l, r, "multiply")?),
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) | (Value::Null, v) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("multiply", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation("multiply", describe(v), describe(vv))),
     }
 }
 
+/// The longest string repetition can build, the same as jq's, so that a large count fails rather
+/// than exhausting memory.
+const MAX_REPEAT_LENGTH: usize = i32::MAX as usize;
+
+// As in jq a string repeated a non-positive number of times is null, and a fractional number
+// of times is rounded up
+fn repeat(s: &str, n: &Number) -> QueryResult {
+    let n = n.as_f64().unwrap_or_default().ceil();
+    if n <= 0.0 {
+        return null();
+    }
+    let len = (s.len() as f64) * n;
+    if len > MAX_REPEAT_LENGTH as f64 {
+        return Err(QueryError::Custom(
+            "Repeat string result too long".to_string(),
+        ));
+    }
+    single(Value::String(s.repeat(n as usize)))
+}
+
 fn div(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => divide_numbers(n, m, |a, b| a / b, |a, b| a / b),
+        (Value::Number(n), Value::Number(m)) => divide_numbers(n, m, exact_div, |a, b| a / b),
         (Value::String(s), Value::String(t)) => single(Value::Array(
             s.split(t).map(|s| Value::String(s.to_string())).collect(),
         )),
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) => single(v.clone()),
-        (v, vv) => Err(QueryError::Operation("divide", type_str(v), type_str(vv))),
+        (v, vv) => Err(QueryError::Operation("divide", describe(v), describe(vv))),
     }
 }
 
 fn modulus(l: &Value, r: &Value) -> QueryResult {
     match (l, r) {
-        (Value::Number(n), Value::Number(m)) => divide_numbers(n, m, |a, b| a % b, |a, b| a % b),
+        (Value::Number(n), Value::Number(m)) => {
+            divide_numbers(n, m, i64::checked_rem, |a, b| a % b)
+        }
         (Value::Null, Value::Null) => null(),
         (v, Value::Null) => single(v.clone()),
         (v, vv) => Err(QueryError::Operation(
             "divide (remainder)",
-            type_str(v),
-            type_str(vv),
+            describe(v),
+            describe(vv),
         )),
     }
 }
@@ -143,18 +253,23 @@ where
     T: IntoIterator<Item = I> + Clone,
     O: FromIterator<I>,
 {
-    a.clone().into_iter().chain(b.clone().into_iter()).collect()
+    a.clone().into_iter().chain(b.clone()).collect()
 }
 
+/// Combines two integers as integers, unless the result would overflow, and anything else as
+/// floats. A float result stays a float even when it's whole, so `2.0 + 1` gives `3.0`.
 fn combine_numbers<F64, I64>(n: &Number, m: &Number, i: I64, f: F64) -> QueryResult
 where
-    I64: Fn(i64, i64) -> i64,
+    I64: Fn(i64, i64) -> Option<i64>,
     F64: Fn(f64, f64) -> f64,
 {
     let num = match (n.as_i64(), m.as_i64()) {
-        (Some(n), Some(m)) => Some(Number::from(i(n, m))),
+        (Some(a), Some(b)) => match i(a, b) {
+            Some(res) => Some(Number::from(res)),
+            None => Number::from_f64(f(a as f64, b as f64)),
+        },
         _ => match (n.as_f64(), m.as_f64()) {
-            (Some(n), Some(m)) => Number::from_f64(f(n, m)),
+            (Some(a), Some(b)) => Number::from_f64(f(a, b)),
             _ => None,
         },
     };
@@ -163,20 +278,30 @@ where
 
 fn divide_numbers<F64, I64>(n: &Number, m: &Number, i: I64, f: F64) -> QueryResult
 where
-    I64: Fn(i64, i64) -> i64,
+    I64: Fn(i64, i64) -> Option<i64>,
     F64: Fn(f64, f64) -> f64,
 {
-    let num = match (n.as_i64(), m.as_i64()) {
-        (Some(_), Some(m)) if m == 0 => None,
-        (Some(n), Some(m)) if n % m == 0 => Some(Number::from(i(n, m))),
-        (Some(n), Some(m)) => Number::from_f64(f(n as f64, m as f64)),
-        _ => match (n.as_f64(), m.as_f64()) {
-            (Some(_), Some(m)) if m == 0f64 => None,
-            (Some(n), Some(m)) => Number::from_f64(f(n, m)),
-            _ => None,
-        },
-    };
-    single(Value::Number(num.ok_or(QueryError::Numerical)?))
+    if m.as_f64() == Some(0.0) {
+        return Err(QueryError::Numerical);
+    }
+    combine_numbers(n, m, i, f)
+}
+
+// Integers that don't divide exactly give a float, so that `1 / 2` is `0.5`
+fn exact_div(a: i64, b: i64) -> Option<i64> {
+    match a.checked_rem(b)? {
+        0 => a.checked_div(b),
+        _ => None,
+    }
+}
+
+/// Merges objects recursively as `*` does, where `op` describes the operation if either isn't an
+/// object. Unlike other operands, an object can't be multiplied by null.
+pub(crate) fn merge(l: &Value, r: &Value, op: &'static str) -> Result<Value, QueryError> {
+    match (l, r) {
+        (Value::Object(o), Value::Object(p)) => Ok(multiply_objects(o, p)),
+        (v, vv) => Err(QueryError::Operation(op, describe(v), describe(vv))),
+    }
 }
 
 fn multiply_objects(l: &Map<String, Value>, r: &Map<String, Value>) -> Value {
@@ -191,6 +316,46 @@ fn multiply_objects(l: &Map<String, Value>, r: &Map<String, Value>) -> Value {
     Value::Object(map)
 }
 
+pub(crate) fn parse_or(input: &str) -> IResult<&str, Query, ParseError> {
+    let (input, left) = parse_and(input)?;
+    let (input, opt) = opt(pair(
+        space::around(value(Sign::Or, keyword("or"))),
+        parse_or,
+    ))(input)?;
+
+    if let Some((sign, right)) = opt {
+        Ok((input, Query::Op(Box::new(Op { left, sign, right }))))
+    } else {
+        Ok((input, left))
+    }
+}
+
+pub(crate) fn parse_and(input: &str) -> IResult<&str, Query, ParseError> {
+    let (input, left) = parse_compare(input)?;
+    let (input, opt) = opt(pair(
+        space::around(value(Sign::And, keyword("and"))),
+        parse_and,
+    ))(input)?;
+
+    if let Some((sign, right)) = opt {
+        Ok((input, Query::Op(Box::new(Op { left, sign, right }))))
+    } else {
+        Ok((input, left))
+    }
+}
+
+// Comparisons don't chain, so `1 < 2 < 3` is not a valid query
+pub(crate) fn parse_compare(input: &str) -> IResult<&str, Query, ParseError> {
+    let (input, left) = parse_add(input)?;
+    let (input, opt) = opt(pair(space::around(parse_compare_sign), parse_add))(input)?;
+
+    if let Some((sign, right)) = opt {
+        Ok((input, Query::Op(Box::new(Op { left, sign, right }))))
+    } else {
+        Ok((input, left))
+    }
+}
+
 pub(crate) fn parse_add(input: &str) -> IResult<&str, Query, ParseError> {
     let (input, left) = parse_mul(input)?;
     let (input, opt) = opt(pair(
@@ -1,20 +1,26 @@
+use std::cell::RefCell;
+
 use crate::{
+    assign::parse_assign,
     combinator::{chain, optional, Chain, Split},
     construction::Construct,
+    format::Format,
+    function::Function,
     index::Index,
-    operators::parse_add,
+    interpolation::Interpolation,
     query::Query,
     raw::Raw,
+    reduce::Reduce,
     space,
 };
 
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alphanumeric1, char},
-    combinator::{all_consuming, map, opt, value},
+    bytes::complete::{tag, take_while},
+    character::complete::{char, satisfy},
+    combinator::{all_consuming, map, not, opt, recognize, value},
     error::{self, ErrorKind},
-    sequence::preceded,
+    sequence::{pair, preceded, terminated},
     IResult,
 };
 use thiserror::Error;
@@ -25,6 +31,46 @@ pub enum ParseError {
     Incomplete(String),
     #[error("Invalid format: {0:?} at {1}")]
     InvalidFormat(ErrorKind, String),
+    /// Parsing failed at a byte offset into the whole of the query.
+    #[error("Invalid syntax at offset {offset} ({kind:?})")]
+    Syntax {
+        kind: ErrorKind,
+        offset: usize,
+        query: String,
+    },
+    /// Slices such as `.[::2]` take a step in some languages, but not in jq.
+    #[error("Slices with a step are not supported: {0:?}")]
+    StepSlice(String),
+}
+
+impl ParseError {
+    // Nom only has the input that was left when parsing failed, which is a suffix of the query
+    fn locate(self, query: &str) -> Self {
+        match self {
+            ParseError::InvalidFormat(kind, rest) => ParseError::Syntax {
+                kind,
+                offset: query.len() - rest.len(),
+                query: query.to_string(),
+            },
+            e => e,
+        }
+    }
+
+    /// Renders the line of the query where parsing failed with a `^` under the offending
+    /// character, if the position is known.
+    pub fn caret(&self) -> Option<String> {
+        match self {
+            ParseError::Syntax { offset, query, .. } => {
+                let start = query[..*offset].rfind('\n').map_or(0, |i| i + 1);
+                let end = query[*offset..]
+                    .find('\n')
+                    .map_or(query.len(), |i| offset + i);
+                let column = query[start..*offset].chars().count();
+                Some(format!("{}\n{}^", &query[start..end], " ".repeat(column)))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl From<nom::Err<ParseError>> for ParseError {
@@ -54,15 +100,40 @@ impl std::str::FromStr for Query {
     }
 }
 
+thread_local! {
+    // The whole of the text being parsed, as the parsers themselves only see what's left of it
+    static SOURCE: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
 pub trait Parseable: Sized {
     fn parser(input: &str) -> IResult<&str, Self, ParseError>;
 
     fn parse(input: &str) -> Result<Self, ParseError> {
-        let (_, output) = all_consuming(Self::parser)(input)?;
+        let previous = SOURCE.with(|s| s.replace(input.to_string()));
+        let result = all_consuming(Self::parser)(input);
+        SOURCE.with(|s| s.replace(previous));
+        let (_, output) = result.map_err(|e| ParseError::from(e).locate(input))?;
         Ok(output)
     }
 }
 
+/// The line of the text being parsed that `rest` starts on, counting from 1. The text is only
+/// known within `Parseable::parse`, so a parser used on its own puts everything on line 1.
+fn line_of(rest: &str) -> usize {
+    SOURCE.with(|s| {
+        let source = s.borrow();
+        match source.len().checked_sub(rest.len()) {
+            Some(offset) if source.ends_with(rest) => source[..offset].matches('\n').count() + 1,
+            _ => 1,
+        }
+    })
+}
+
+fn parse_loc(input: &str) -> IResult<&str, Query, ParseError> {
+    let line = line_of(input);
+    value(Query::Loc(line), keyword("$__loc__"))(input)
+}
+
 impl Parseable for Query {
     fn parser(input: &str) -> IResult<&str, Self, ParseError> {
         if input.is_empty() {
@@ -83,7 +154,7 @@ pub(crate) fn parse_pipe(input: &str) -> IResult<&str, Query, ParseError> {
 }
 
 pub(crate) fn parse_split(input: &str) -> IResult<&str, Query, ParseError> {
-    let (input, left) = parse_add(input)?;
+    let (input, left) = parse_assign(input)?;
     let (input, opt) = opt(preceded(space::around(char(',')), parse_split))(input)?;
     if let Some(right) = opt {
         Ok((input, Query::Split(Box::new(Split(left, right)))))
@@ -99,7 +170,16 @@ pub(crate) fn parse_init(input: &str) -> IResult<&str, Query, ParseError> {
             map(Construct::parser, Query::Contruct),
             preceded(char('.'), alt((parse_index, parse_iterator))),
         ))),
+        map(Interpolation::parser, Query::Interpolation),
         map(Raw::parser, Query::Raw),
+        map(Format::parser, Query::Format),
+        map(Reduce::parser, |r| Query::Reduce(Box::new(r))),
+        chain(optional(map(Function::parser, Query::Function))),
+        chain(parse_loc),
+        chain(optional(map(
+            preceded(char('$'), parse_identifier),
+            |s: &str| Query::Variable(s.to_string()),
+        ))),
         value(Query::Recurse, tag("..")),
         value(Query::Identity, char('.')),
     )))(input)
@@ -109,12 +189,34 @@ pub(crate) fn parse_chain(input: &str) -> IResult<&str, Query, ParseError> {
     chain(alt((parse_index_shorthand, parse_index, parse_iterator)))(input)
 }
 
+pub(crate) fn parse_identifier(input: &str) -> IResult<&str, &str, ParseError> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+    ))(input)
+}
+
+/// Whether the whole of `s` is an identifier, so it can be written bare, e.g. as `.foo`.
+pub(crate) fn is_identifier(s: &str) -> bool {
+    all_consuming(parse_identifier)(s).is_ok()
+}
+
+/// Matches a reserved word, as long as it isn't just the start of a longer identifier.
+pub(crate) fn keyword<'a>(
+    word: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, ParseError> {
+    terminated(
+        tag(word),
+        not(satisfy(|c| c.is_ascii_alphanumeric() || c == '_')),
+    )
+}
+
 fn parse_index(input: &str) -> IResult<&str, Query, ParseError> {
     optional(map(Index::parser, Query::Index))(input)
 }
 
 fn parse_index_shorthand(input: &str) -> IResult<&str, Query, ParseError> {
-    optional(map(preceded(char('.'), alphanumeric1), |s: &str| {
+    optional(map(preceded(char('.'), parse_identifier), |s: &str| {
         Query::Index(Index::String(s.to_string()))
     }))(input)
 }
@@ -137,11 +239,272 @@ mod tests {
 
     #[test]
     fn iterator() {
-        assert!("[]".parse::<Query>().is_err());
+        assert!(!matches!("[]".parse::<Query>(), Ok(Query::Iterator)));
         assert!(".[".parse::<Query>().is_err());
         assert!(".]".parse::<Query>().is_err());
         assert!(".[].[]".parse::<Query>().is_err());
 
         assert_eq!(Query::Iterator, ".[]".parse().unwrap());
     }
+
+    #[test]
+    fn variable() {
+        assert!("$".parse::<Query>().is_err());
+        assert!("$1".parse::<Query>().is_err());
+        assert!("$ foo".parse::<Query>().is_err());
+
+        assert_eq!(Query::Variable("ENV".to_string()), "$ENV".parse().unwrap());
+        assert_eq!(
+            Query::Chain(Box::new(Chain(
+                Query::Variable("foo".to_string()),
+                Query::Index(Index::String("bar".to_string()))
+            ))),
+            "$foo.bar".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            ".foo | .bar".parse::<Query>().unwrap(),
+            ".foo # grab foo\n| .bar".parse().unwrap()
+        );
+        assert_eq!(
+            ".foo".parse::<Query>().unwrap(),
+            ".foo # trailing".parse().unwrap()
+        );
+        assert_eq!(
+            "[1, 2]".parse::<Query>().unwrap(),
+            "[1, # one\n 2 # two\n]".parse().unwrap()
+        );
+        assert_eq!(
+            Query::Raw(Raw::parse("\"a # b\"").unwrap()),
+            "\"a # b\"".parse().unwrap()
+        );
+        assert!(".foo # | .bar".parse::<Query>().is_ok());
+        assert_eq!(
+            ".foo".parse::<Query>().unwrap(),
+            "# leading\n.foo".parse().unwrap()
+        );
+    }
+
+    fn assert_round_trip(query: &str) {
+        let q: Query = query.parse().unwrap();
+        let displayed = q.to_string();
+        assert_eq!(
+            q,
+            displayed.parse().unwrap(),
+            "{} was displayed as {}",
+            query,
+            displayed
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            ".foo.bar",
+            ".foo|.bar".parse::<Query>().unwrap().to_string()
+        );
+        assert_eq!(". | .foo", ".|.foo".parse::<Query>().unwrap().to_string());
+        assert_eq!(".foo.bar", ".foo.bar".parse::<Query>().unwrap().to_string());
+        assert_eq!(
+            ".[\"a b\"][0][1:]?",
+            ".[\"a b\"][0][1:]?".parse::<Query>().unwrap().to_string()
+        );
+        assert_eq!(
+            "{a: .a, \"b c\": 1, (.d): [.[], ..]}",
+            "{a,\"b c\":1,(.d):[.[],..]}"
+                .parse::<Query>()
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "reduce .[] as $x (0; . + $x)",
+            "reduce .[] as $x (0;.+$x)"
+                .parse::<Query>()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let atoms = [
+            ".",
+            "..",
+            ".foo",
+            ".[\"a b\"]",
+            ".[\"a\", \"b\"]",
+            ".[4, 2]",
+            ".[\"a\\\"b\"]",
+            "{\"a\\nb\": 1}",
+            "\"tab\\tquote\\\"\"",
+            "\"\\\"\\(.a)\\n\"",
+            ".[-1]",
+            ".[2:]",
+            ".[:-2]",
+            ".[]",
+            ".foo?",
+            ".[]?",
+            ".a.b[0]",
+            "$x",
+            "$x.a",
+            "$__loc__",
+            "1",
+            "-1.5",
+            "1e100",
+            "\"s\"",
+            "null",
+            "true",
+            "[.a]",
+            "[.a][0]",
+            "[]",
+            "{}",
+            "{a, b: 1, \"c d\": .e, (.f): $g}",
+            "length",
+            "first(.[])",
+            "limit(1; .[])",
+            "@html",
+            "\"a \\(.b) c\"",
+            "reduce .[] as $x (0; . + $x)",
+        ];
+        let templates = [
+            "{}",
+            "{} | {}",
+            "{}, {}",
+            "{} + {}",
+            "{} - {}",
+            "{} * {}",
+            "{} / {}",
+            "{} % {}",
+            "{} == {}",
+            "{} <= {}",
+            "{} and {}",
+            "{} or {}",
+            "{} | {} | {}",
+            "{}, {} | {}",
+            "{} + {} * {}",
+            "{} * {} + {}",
+            "{} - {} - {}",
+            "{} or {} and {}",
+            "[{} | {}]",
+            "{{k: {}}}",
+            "f({}; {})",
+            "reduce {} as $v ({}; {})",
+            "\"\\({}) and \\({})\"",
+        ];
+        for template in templates.iter() {
+            for a in atoms.iter() {
+                for b in atoms.iter() {
+                    let query = template.replacen("{}", a, 1).replacen("{}", b, 1);
+                    let query = query
+                        .replacen("{}", a, 1)
+                        .replace("{{", "{")
+                        .replace("}}", "}");
+                    if query.parse::<Query>().is_ok() {
+                        assert_round_trip(&query);
+                    }
+                }
+            }
+        }
+
+        for query in [
+            ".a = 1",
+            ".a |= . + 1",
+            ".a += 1",
+            ".a //= 1",
+            ".a %= 2",
+            ".a.b? = .c, .d",
+        ]
+        .iter()
+        {
+            assert_round_trip(query);
+        }
+    }
+
+    #[test]
+    fn error_offset() {
+        let err = ".a | | .b".parse::<Query>().unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { offset: 3, .. }));
+        assert_eq!(".a | | .b\n   ^", err.caret().unwrap());
+
+        let err = ".é ]".parse::<Query>().unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { offset: 1, .. }));
+        assert_eq!(".é ]\n ^", err.caret().unwrap());
+
+        let err = ".[0] ]".parse::<Query>().unwrap_err();
+        assert_eq!("Invalid syntax at offset 5 (Eof)", err.to_string());
+        assert_eq!(".[0] ]\n     ^", err.caret().unwrap());
+
+        let err = ".a\n| .b\n| ]".parse::<Query>().unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { offset: 8, .. }));
+        assert_eq!("| ]\n^", err.caret().unwrap());
+
+        // Failures inside brackets are reported where they happened, not at the bracket
+        let err = "[1, 2".parse::<Query>().unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { offset: 5, .. }));
+        assert_eq!("[1, 2\n     ^", err.caret().unwrap());
+
+        let err = "{a: }".parse::<Query>().unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { offset: 4, .. }));
+        assert_eq!("{a: }\n    ^", err.caret().unwrap());
+
+        let err = ".a | {b: [1, 2}".parse::<Query>().unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { offset: 14, .. }));
+    }
+
+    #[test]
+    fn multi_line() {
+        let chain = |a: &str, b: Query| {
+            Query::Chain(Box::new(Chain(
+                Query::Index(Index::String(a.to_string())),
+                b,
+            )))
+        };
+        let expected = chain(
+            "a",
+            chain("b", Query::Index(Index::String("c".to_string()))),
+        );
+        assert_eq!(expected, ".a\n| .b\n| .c".parse().unwrap());
+        assert_eq!(expected, "\t.a |\r\n\t.b |\n\n\t.c\n".parse().unwrap());
+    }
+
+    #[test]
+    fn loc() {
+        assert_eq!(Query::Loc(1), "$__loc__".parse().unwrap());
+        assert_eq!(
+            Query::Variable("__loc__x".to_string()),
+            "$__loc__x".parse().unwrap()
+        );
+        let q: Query = "\"a\nb\nc\", $__loc__.line".parse().unwrap();
+        match q {
+            Query::Split(split) => assert_eq!(
+                Query::Chain(Box::new(Chain(
+                    Query::Loc(3),
+                    Query::Index(Index::String("line".to_string()))
+                ))),
+                split.1
+            ),
+            q => panic!("Unexpected query {:?}", q),
+        }
+
+        let q: Query = ".a |\n $__loc__".parse().unwrap();
+        assert_eq!(
+            Query::Chain(Box::new(Chain(
+                Query::Index(Index::String("a".to_string())),
+                Query::Loc(2)
+            ))),
+            q
+        );
+
+        // Only the text before it gives the line, so it isn't kept when displayed
+        let q: Query = "\"\n\", $__loc__".parse().unwrap();
+        assert_eq!("\"\\n\", $__loc__", q.to_string());
+        assert_ne!(q, q.to_string().parse().unwrap());
+        assert_eq!(
+            Ok(("", Query::Loc(1))),
+            Query::parser("$__loc__").map_err(|_| ())
+        );
+    }
 }
@@ -0,0 +1,463 @@
+use std::convert::TryFrom;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    combinator::{Chain, Optional, Split},
+    describe, describe_slice,
+    index::Index,
+    query::{Executable, Query},
+    range::Range,
+    scope::Scope,
+    sorted_entries, type_str, QueryError,
+};
+
+/// A sequence of object keys, array indices and array slices locating a value within another
+/// value. Slices are objects with `start` and `end` bounds.
+pub(crate) type Path = Vec<Value>;
+
+/// The largest index an array can be extended to by assignment, the same as jq's, so that a
+/// stray large index fails rather than allocating gigabytes of `null`s.
+const MAX_ARRAY_INDEX: usize = 536_870_911;
+
+impl Query {
+    /// Whether the query only selects locations within its input, making it usable on the left
+    /// hand side of an assignment. This is checked without executing the query.
+    pub fn is_path_expression(&self) -> bool {
+        match self {
+            Query::Empty | Query::Identity | Query::Index(_) | Query::Iterator | Query::Recurse => {
+                true
+            }
+            Query::Split(split) => split.0.is_path_expression() && split.1.is_path_expression(),
+            Query::Chain(chain) => chain.0.is_path_expression() && chain.1.is_path_expression(),
+            Query::Optional(opt) => opt.0.is_path_expression(),
+            Query::Function(f) => matches!(
+                (f.name.as_str(), f.args.len()),
+                ("empty", 0) | ("error", 0) | ("error", 1) | ("recurse", 0)
+            ),
+            _ => false,
+        }
+    }
+
+    /// Evaluates the query as a path expression, returning the value at each selected path or
+    /// `None` where the path doesn't exist. Unlike executing the query, this tells a missing key
+    /// apart from one whose value is `null`.
+    pub fn locate(&self, value: &Value, scope: &Scope) -> Result<Vec<Option<Value>>, QueryError> {
+        paths(self, value, scope)?
+            .iter()
+            .map(|(path, _)| lookup(value, path))
+            .collect()
+    }
+}
+
+/// Evaluates a query as a path expression, returning each selected path alongside the value
+/// found there. Paths may point at missing values, in which case the value is `null`.
+pub(crate) fn paths(
+    query: &Query,
+    value: &Value,
+    scope: &Scope,
+) -> Result<Vec<(Path, Value)>, QueryError> {
+    paths_from(query, Vec::new(), value, scope)
+}
+
+fn paths_from(
+    query: &Query,
+    prefix: Path,
+    value: &Value,
+    scope: &Scope,
+) -> Result<Vec<(Path, Value)>, QueryError> {
+    let extend = |key: Value, vv: Value| {
+        let mut path = prefix.clone();
+        path.push(key);
+        (path, vv)
+    };
+
+    match query {
+        Query::Empty => Ok(Vec::new()),
+        Query::Identity => Ok(vec![(prefix, value.clone())]),
+        Query::Index(i) => {
+            let key = match i {
+                Index::String(s) => Value::String(s.clone()),
+                Index::Integer(n) => Value::from(*n),
+                Index::Slice(r) => match value {
+                    Value::Null | Value::Array(_) => r.to_value(),
+                    vv => return Err(QueryError::Index(describe(vv), "slice")),
+                },
+                Index::Keys(keys) => {
+                    let mut res = Vec::new();
+                    for k in keys {
+                        let q = Query::Index(k.clone());
+                        res.extend(paths_from(&q, prefix.clone(), value, scope)?);
+                    }
+                    return Ok(res);
+                }
+            };
+            let vv = match value {
+                Value::Null => Value::Null,
+                vv => i.execute_in(vv, scope)?.remove(0),
+            };
+            Ok(vec![extend(key, vv)])
+        }
+        Query::Iterator => match value {
+            Value::Array(arr) => Ok(arr
+                .iter()
+                .enumerate()
+                .map(|(i, vv)| extend(Value::from(i), vv.clone()))
+                .collect()),
+            Value::Object(map) => Ok(sorted_entries(map)
+                .into_iter()
+                .map(|(k, vv)| extend(Value::String(k.clone()), vv.clone()))
+                .collect()),
+            vv => Err(QueryError::Iterate(describe(vv))),
+        },
+        // Walked with an explicit stack in pre-order, so deeply nested input can't overflow
+        Query::Recurse => {
+            let mut res = Vec::new();
+            let mut stack = vec![(prefix, value.clone())];
+            while let Some((path, vv)) = stack.pop() {
+                let children = paths_from(&Query::Iterator, path.clone(), &vv, scope);
+                stack.extend(children.unwrap_or_default().into_iter().rev());
+                res.push((path, vv));
+            }
+            Ok(res)
+        }
+        Query::Split(split) => {
+            let Split(left, right) = split.as_ref();
+            let mut res = paths_from(left, prefix.clone(), value, scope)?;
+            res.extend(paths_from(right, prefix, value, scope)?);
+            Ok(res)
+        }
+        Query::Chain(chain) => {
+            let Chain(left, right) = chain.as_ref();
+            let mut res = Vec::new();
+            for (path, vv) in paths_from(left, prefix, value, scope)? {
+                res.extend(paths_from(right, path, &vv, scope)?);
+            }
+            Ok(res)
+        }
+        Query::Optional(opt) => {
+            let Optional(inner) = opt.as_ref();
+            Ok(paths_from(inner, prefix, value, scope).unwrap_or_default())
+        }
+        Query::Function(f) if f.name == "recurse" && f.args.is_empty() => {
+            paths_from(&Query::Recurse, prefix, value, scope)
+        }
+        q => match q.execute_in(value, scope)?.first() {
+            Some(vv) => Err(QueryError::InvalidPath(vv.to_string())),
+            None => Ok(Vec::new()),
+        },
+    }
+}
+
+/// Looks up the value at `path`, treating anything missing along the way as `null`.
+pub(crate) fn getpath(root: &Value, path: &[Value]) -> Result<Value, QueryError> {
+    lookup(root, path).map(Option::unwrap_or_default)
+}
+
+/// Looks up the value at `path`, or `None` if anything along the way is missing.
+fn lookup(root: &Value, path: &[Value]) -> Result<Option<Value>, QueryError> {
+    let mut current = root;
+    for (i, key) in path.iter().enumerate() {
+        current = match (current, key) {
+            (Value::Null, _) => return Ok(None),
+            (Value::Object(map), Value::String(k)) => match map.get(k) {
+                Some(vv) => vv,
+                None => return Ok(None),
+            },
+            (Value::Array(arr), Value::Number(n)) => {
+                let i = n.as_i64().ok_or(QueryError::Numerical)?;
+                let i = if i < 0 { i + arr.len() as i64 } else { i };
+                match usize::try_from(i).ok().and_then(|i| arr.get(i)) {
+                    Some(vv) => vv,
+                    None => return Ok(None),
+                }
+            }
+            (Value::Array(arr), Value::Object(_)) => {
+                let range = slice(key)?.normalize(arr.len());
+                let sliced = Value::Array(arr[range].to_vec());
+                return lookup(&sliced, &path[i + 1..]);
+            }
+            (v, k) => return Err(QueryError::Index(describe(v), type_str(k))),
+        };
+    }
+    Ok(Some(current.clone()))
+}
+
+/// Returns a copy of `root` with the value at `path` replaced, creating any missing objects
+/// and arrays along the way.
+pub(crate) fn setpath(root: &Value, path: &[Value], value: Value) -> Result<Value, QueryError> {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(value),
+    };
+
+    match (root, key) {
+        (Value::Object(_), Value::String(k)) | (Value::Null, Value::String(k)) => {
+            let mut map = match root {
+                Value::Object(map) => map.clone(),
+                _ => Map::new(),
+            };
+            let child = setpath(map.get(k).unwrap_or(&Value::Null), rest, value)?;
+            map.insert(k.clone(), child);
+            Ok(Value::Object(map))
+        }
+        (Value::Array(_), Value::Number(n)) | (Value::Null, Value::Number(n)) => {
+            let mut arr = match root {
+                Value::Array(arr) => arr.clone(),
+                _ => Vec::new(),
+            };
+            // Negative indices count back from the end, but can't reach before the start
+            let i = n.as_i64().ok_or(QueryError::Numerical)?;
+            let i = if i < 0 { i + arr.len() as i64 } else { i };
+            let i = usize::try_from(i).map_err(|_| {
+                QueryError::Custom("Out of bounds negative array index".to_string())
+            })?;
+            if i > MAX_ARRAY_INDEX {
+                return Err(QueryError::Custom("Array index too large".to_string()));
+            }
+            if i >= arr.len() {
+                arr.resize(i + 1, Value::Null);
+            }
+            arr[i] = setpath(&arr[i], rest, value)?;
+            Ok(Value::Array(arr))
+        }
+        // The slice is replaced with the array it's set to, which may differ in length
+        (Value::Array(_), Value::Object(_)) | (Value::Null, Value::Object(_)) => {
+            let mut arr = match root {
+                Value::Array(arr) => arr.clone(),
+                _ => Vec::new(),
+            };
+            let range = slice(key)?.normalize(arr.len());
+            let sliced = Value::Array(arr[range.clone()].to_vec());
+            match setpath(&sliced, rest, value)? {
+                Value::Array(replacement) => {
+                    arr.splice(range, replacement);
+                    Ok(Value::Array(arr))
+                }
+                vv => Err(QueryError::Unsupported(
+                    type_str(&vv),
+                    "assigned to a slice",
+                )),
+            }
+        }
+        (v, k) => Err(QueryError::Index(describe(v), type_str(k))),
+    }
+}
+
+/// Returns a copy of `root` without the values at any of `paths`. Every path is resolved
+/// against `root` itself, so deleting one element doesn't shift the indices of the others.
+pub(crate) fn delpaths(root: &Value, paths: &[&[Value]]) -> Result<Value, QueryError> {
+    if paths.is_empty() {
+        return Ok(root.clone());
+    }
+    if paths.iter().any(|path| path.is_empty()) {
+        return Ok(Value::Null);
+    }
+
+    match root {
+        Value::Null => Ok(Value::Null),
+        Value::Object(map) => {
+            let mut nested: Vec<(&String, Vec<&[Value]>)> = Vec::new();
+            for path in paths {
+                let key = match &path[0] {
+                    Value::String(k) => k,
+                    k => return Err(QueryError::Index(describe(root), type_str(k))),
+                };
+                match nested.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, rests)) => rests.push(&path[1..]),
+                    None => nested.push((key, vec![&path[1..]])),
+                }
+            }
+
+            let mut map = map.clone();
+            for (key, rests) in nested {
+                if rests.iter().any(|rest| rest.is_empty()) {
+                    map.remove(key);
+                } else if let Some(child) = map.get(key) {
+                    let child = delpaths(child, &rests)?;
+                    map.insert(key.clone(), child);
+                }
+            }
+            Ok(Value::Object(map))
+        }
+        Value::Array(arr) => {
+            let mut removed = vec![false; arr.len()];
+            let mut nested: Vec<(usize, Vec<&[Value]>)> = Vec::new();
+            for path in paths {
+                match target(arr, path)? {
+                    Some((Target::At(i), [])) => removed[i] = true,
+                    Some((Target::At(i), rest)) => match nested.iter_mut().find(|(j, _)| *j == i) {
+                        Some((_, rests)) => rests.push(rest),
+                        None => nested.push((i, vec![rest])),
+                    },
+                    Some((Target::Within(range), _)) => removed[range].fill(true),
+                    None => {}
+                }
+            }
+
+            let mut arr = arr.clone();
+            for (i, rests) in nested {
+                arr[i] = delpaths(&arr[i], &rests)?;
+            }
+            Ok(Value::Array(
+                arr.into_iter()
+                    .zip(removed)
+                    .filter(|(_, removed)| !removed)
+                    .map(|(vv, _)| vv)
+                    .collect(),
+            ))
+        }
+        v => Err(QueryError::Index(describe(v), type_str(&paths[0][0]))),
+    }
+}
+
+enum Target {
+    At(usize),
+    Within(std::ops::Range<usize>),
+}
+
+/// Resolves the leading indices and slices of a path into an array, with any
+/// slice of a slice resolved to the part of the array it covers. The rest of the path is given
+/// alongside, which is always empty for a slice, and nothing is targeted when an index is out of
+/// bounds.
+fn target<'a>(
+    arr: &[Value],
+    path: &'a [Value],
+) -> Result<Option<(Target, &'a [Value])>, QueryError> {
+    let mut within = 0..arr.len();
+    let mut rest = path;
+    while let Some((key, tail)) = rest.split_first() {
+        match key {
+            Value::Number(n) => {
+                let i = n.as_i64().ok_or(QueryError::Numerical)?;
+                let i = if i < 0 { i + within.len() as i64 } else { i };
+                return Ok(usize::try_from(i)
+                    .ok()
+                    .filter(|i| *i < within.len())
+                    .map(|i| (Target::At(within.start + i), tail)));
+            }
+            Value::Object(_) => {
+                let range = slice(key)?.normalize(within.len());
+                within = within.start + range.start..within.start + range.end;
+                rest = tail;
+            }
+            k => return Err(QueryError::Index(describe_slice(&arr[within]), type_str(k))),
+        }
+    }
+    Ok(Some((Target::Within(within), rest)))
+}
+
+fn slice(key: &Value) -> Result<Range, QueryError> {
+    Range::from_value(key).ok_or_else(|| QueryError::InvalidPath(key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(query: &str, value: &str) -> Vec<Value> {
+        let q: Query = query.parse().unwrap();
+        let v: Value = serde_json::from_str(value).unwrap();
+        paths(&q, &v, &Scope::new())
+            .unwrap()
+            .into_iter()
+            .map(|(p, _)| Value::Array(p))
+            .collect()
+    }
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn path_expressions() {
+        assert_eq!(vec![json("[]")], eval(".", "1"));
+        assert_eq!(vec![json(r#"["a","b"]"#)], eval(".a.b", "null"));
+        assert_eq!(
+            vec![json(r#"["a",0]"#), json(r#"["a",1]"#)],
+            eval(".a[]", r#"{"a":[1,2]}"#)
+        );
+        assert_eq!(
+            vec![json("[]"), json("[0]"), json("[0,0]"), json("[1]")],
+            eval("..", "[[1],2]")
+        );
+        assert_eq!(eval("..", "[[1],2]"), eval("recurse", "[[1],2]"));
+        assert_eq!(
+            vec![json(r#"["a","b"]"#), json(r#"["a","c"]"#)],
+            eval(r#".a["b", "c"]"#, "null")
+        );
+        assert_eq!(
+            vec![json(r#"[{"start":1,"end":null}]"#)],
+            eval(".[1:]", "[1,2,3]")
+        );
+        assert_eq!(
+            vec![json(r#"["a"]"#), json("[0]")],
+            eval(".a, .[0]", "null")
+        );
+        assert!(eval(".a?", "[]").is_empty());
+        assert!(eval("empty", "[]").is_empty());
+    }
+
+    #[test]
+    fn is_path_expression() {
+        for query in &[
+            ".", ".a[0].b", ".[]", ".[1:]?", "..", ".a, .b[]", ".a | .b", "empty",
+        ] {
+            let q: Query = query.parse().unwrap();
+            assert!(q.is_path_expression(), "{}", query);
+        }
+        for query in &[
+            ".a + 1", "length", "1", "[.a]", "{a}", "$x", ".a = 1", ".a | 1",
+        ] {
+            let q: Query = query.parse().unwrap();
+            assert!(!q.is_path_expression(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn invalid_path_expressions() {
+        let v = json(r#"{"a":1}"#);
+        for query in &["1", ".a + 1", "[.a]"] {
+            let q: Query = query.parse().unwrap();
+            assert!(paths(&q, &v, &Scope::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn get_path() {
+        let v = json(r#"{"a":[1,{"b":2}]}"#);
+        assert_eq!(v, getpath(&v, &[]).unwrap());
+        assert_eq!(
+            json("2"),
+            getpath(&v, &[json(r#""a""#), json("-1"), json(r#""b""#)]).unwrap()
+        );
+        assert_eq!(
+            json("null"),
+            getpath(&v, &[json(r#""x""#), json("0"), json(r#""y""#)]).unwrap()
+        );
+        assert_eq!(
+            json("null"),
+            getpath(&v, &[json(r#""a""#), json("5")]).unwrap()
+        );
+        assert!(getpath(&v, &[json("0")]).is_err());
+    }
+
+    #[test]
+    fn set_path() {
+        assert_eq!(json("5"), setpath(&json("null"), &[], json("5")).unwrap());
+        assert_eq!(
+            json(r#"{"a":{"b":5}}"#),
+            setpath(&json("null"), &[json(r#""a""#), json(r#""b""#)], json("5")).unwrap()
+        );
+        assert_eq!(
+            json(r#"[1,null,5]"#),
+            setpath(&json("[1]"), &[json("2")], json("5")).unwrap()
+        );
+        assert_eq!(
+            json(r#"{"a":[5],"b":1}"#),
+            setpath(&json(r#"{"b":1}"#), &[json(r#""a""#), json("0")], json("5")).unwrap()
+        );
+        assert!(setpath(&json("[]"), &[json(r#""a""#)], json("5")).is_err());
+        assert!(setpath(&json("{}"), &[json("0")], json("5")).is_err());
+    }
+}
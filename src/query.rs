@@ -1,13 +1,20 @@
 use crate::{
+    assign::Assign,
     combinator::{Chain, Optional, Split},
     construction::Construct,
-    empty,
+    describe, empty,
+    format::Format,
+    function::Function,
     index::Index,
+    interpolation::Interpolation,
     operators::Op,
     raw::Raw,
-    single, type_str, QueryError, QueryResult,
+    reduce::Reduce,
+    scope::Scope,
+    single, sorted_entries, QueryError, QueryResult,
 };
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::{collections::HashMap, fmt, iter};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Query {
@@ -22,55 +29,203 @@ pub enum Query {
     Optional(Box<Optional>),
     Raw(Raw),
     Op(Box<Op>),
+    Function(Function),
+    Variable(String),
+    Assign(Box<Assign>),
+    Format(Format),
+    Interpolation(Interpolation),
+    Reduce(Box<Reduce>),
+    /// `$__loc__`, with the line of the query it appears on. The line depends on what comes
+    /// before it in the text, so it's the one query that doesn't survive being displayed and
+    /// parsed again.
+    Loc(usize),
 }
 
+/// Queries are written back in jq syntax that parses to the same query, except that `$__loc__`
+/// is always written without the line it was on.
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Query::Empty => Ok(()),
+            Query::Identity => write!(f, "."),
+            Query::Index(i) => write!(f, "{}", i),
+            Query::Iterator => write!(f, ".[]"),
+            Query::Recurse => write!(f, ".."),
+            Query::Split(s) => write!(f, "{}", s),
+            Query::Chain(c) => write!(f, "{}", c),
+            Query::Contruct(c) => write!(f, "{}", c),
+            Query::Optional(o) => write!(f, "{}", o),
+            Query::Raw(r) => write!(f, "{}", r),
+            Query::Op(op) => write!(f, "{}", op),
+            Query::Function(func) => write!(f, "{}", func),
+            Query::Variable(name) => write!(f, "${}", name),
+            Query::Assign(a) => write!(f, "{}", a),
+            Query::Format(fmt) => write!(f, "{}", fmt),
+            Query::Interpolation(i) => write!(f, "{}", i),
+            Query::Reduce(r) => write!(f, "{}", r),
+            Query::Loc(_) => write!(f, "$__loc__"),
+        }
+    }
+}
+
+/// Results produced one at a time, borrowing from the query that produces them.
+pub type QueryIter<'a> = Box<dyn Iterator<Item = Result<Value, QueryError>> + 'a>;
+
 pub trait Executable {
-    fn execute(&self, value: &Value) -> QueryResult;
+    /// Executes against a value in a fresh root scope.
+    fn execute(&self, value: &Value) -> QueryResult {
+        self.execute_in(value, &Scope::new())
+    }
+
+    /// Lazily executes against a value in a fresh root scope, so that a consumer can stop
+    /// before every result has been computed.
+    fn execute_iter<'a>(&'a self, value: &Value) -> QueryIter<'a> {
+        self.execute_iter_in(value.clone(), Scope::new())
+    }
+
+    /// Lazily executes against a value in the given scope. By default all of the results are
+    /// computed up front.
+    fn execute_iter_in<'a>(&'a self, value: Value, scope: Scope) -> QueryIter<'a> {
+        into_iter(self.execute_in(&value, &scope))
+    }
+
+    /// Executes against a value in a fresh root scope with the given variables bound.
+    fn execute_with_vars(&self, value: &Value, vars: &HashMap<String, Value>) -> QueryResult {
+        let scope = Scope::new().with_variables(vars.clone());
+        self.execute_in(value, &scope)
+    }
+
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult;
 }
 
 impl Executable for Query {
     fn execute(&self, value: &Value) -> QueryResult {
+        self.execute_iter(value).collect()
+    }
+
+    // Only the queries that route values from one part to another are lazy, other queries are
+    // computed in full as soon as they're reached
+    fn execute_iter_in<'a>(&'a self, value: Value, scope: Scope) -> QueryIter<'a> {
+        match self {
+            Query::Identity => Box::new(iter::once(Ok(value))),
+            Query::Iterator => match value {
+                Value::Array(arr) => Box::new(arr.into_iter().map(Ok)),
+                Value::Object(map) => {
+                    let mut entries: Vec<_> = map.into_iter().collect();
+                    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    Box::new(entries.into_iter().map(|(_, vv)| Ok(vv)))
+                }
+                vv => Box::new(iter::once(Err(QueryError::Iterate(describe(&vv))))),
+            },
+            Query::Recurse => Box::new(Descendants::new(value).map(Ok)),
+            Query::Split(split) => {
+                let Split(left, right) = split.as_ref();
+                let rest = iter::once((value.clone(), scope.clone()))
+                    .flat_map(move |(value, scope)| right.execute_iter_in(value, scope));
+                Box::new(left.execute_iter_in(value, scope.clone()).chain(rest))
+            }
+            Query::Chain(chain) => {
+                let Chain(left, right) = chain.as_ref();
+                Box::new(left.execute_iter_in(value, scope.clone()).flat_map(
+                    move |res| -> QueryIter<'a> {
+                        match res {
+                            Ok(vv) => right.execute_iter_in(vv, scope.clone()),
+                            Err(e) => Box::new(iter::once(Err(e))),
+                        }
+                    },
+                ))
+            }
+            Query::Function(f) => f.execute_iter_in(value, scope),
+            q => into_iter(q.execute_in(&value, &scope)),
+        }
+    }
+
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
         match self {
             Query::Empty => empty(),
             Query::Identity => single(value.clone()),
             Query::Iterator => iterate(value),
             Query::Recurse => recurse(value),
-            Query::Index(i) => i.execute(value),
-            Query::Split(split) => split.execute(value),
-            Query::Chain(chain) => chain.execute(value),
-            Query::Contruct(c) => c.execute(value),
-            Query::Optional(opt) => opt.execute(value),
-            Query::Raw(r) => r.execute(value),
-            Query::Op(op) => op.execute(value),
+            Query::Index(i) => i.execute_in(value, scope),
+            Query::Split(split) => split.execute_in(value, scope),
+            Query::Chain(chain) => chain.execute_in(value, scope),
+            Query::Contruct(c) => c.execute_in(value, scope),
+            Query::Optional(opt) => opt.execute_in(value, scope),
+            Query::Raw(r) => r.execute_in(value, scope),
+            Query::Op(op) => op.execute_in(value, scope),
+            Query::Function(f) => f.execute_in(value, scope),
+            Query::Assign(a) => a.execute_in(value, scope),
+            Query::Format(f) => f.execute_in(value, scope),
+            Query::Interpolation(i) => i.execute_in(value, scope),
+            Query::Reduce(r) => r.execute_in(value, scope),
+            Query::Loc(line) => single(json!({"file": "<stdin>", "line": line})),
+            Query::Variable(name) => match scope.get(name) {
+                Some(v) => single(v.clone()),
+                None => Err(QueryError::UnknownVariable(name.clone())),
+            },
         }
     }
 }
 
+pub(crate) fn into_iter<'a>(result: QueryResult) -> QueryIter<'a> {
+    match result {
+        Ok(results) => Box::new(results.into_iter().map(Ok)),
+        Err(e) => Box::new(iter::once(Err(e))),
+    }
+}
+
+// As in jq only arrays and objects can be iterated, so `null | .[]` is an error rather than
+// producing nothing. `.[]?` can be used to skip anything that can't be iterated.
 fn iterate(v: &Value) -> QueryResult {
     match v {
         Value::Array(arr) => Ok(arr.clone()),
-        Value::Object(map) => Ok(map.values().into_iter().cloned().collect()),
-        v => Err(QueryError::Iterate(type_str(v))),
+        Value::Object(map) => Ok(sorted_entries(map)
+            .into_iter()
+            .map(|(_, vv)| vv.clone())
+            .collect()),
+        v => Err(QueryError::Iterate(describe(v))),
     }
 }
 
 fn recurse(v: &Value) -> QueryResult {
-    let children: Vec<_> = match v {
-        Value::Array(arr) => arr.iter().collect(),
-        Value::Object(map) => map.values().into_iter().collect(),
-        vv => return single(vv.clone()),
-    };
-
-    let mut res = vec![v.clone()];
-    res.extend(iterate_results(children.iter().map(|vv| recurse(vv)))?);
-    Ok(res)
+    Ok(Descendants::new(v.clone()).collect())
+}
+
+/// Walks a value and everything within it depth first, with each value cloned only once as
+/// its parent is emitted. Only the pending siblings along the current branch are held.
+pub(crate) struct Descendants(Vec<Value>);
+
+impl Descendants {
+    pub(crate) fn new(v: Value) -> Self {
+        Descendants(vec![v])
+    }
+}
+
+impl Iterator for Descendants {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        let v = self.0.pop()?;
+        match &v {
+            Value::Array(arr) => self.0.extend(arr.iter().rev().cloned()),
+            Value::Object(map) => self.0.extend(
+                sorted_entries(map)
+                    .into_iter()
+                    .rev()
+                    .map(|(_, vv)| vv.clone()),
+            ),
+            _ => {}
+        }
+        Some(v)
+    }
 }
 
 pub(crate) fn iterate_values<'a, I: IntoIterator<Item = &'a Value>>(
     iter: I,
     next: &Query,
+    scope: &Scope,
 ) -> QueryResult {
-    iterate_results(iter.into_iter().map(|vv| next.execute(vv)))
+    iterate_results(iter.into_iter().map(|vv| next.execute_in(vv, scope)))
 }
 
 pub(crate) fn iterate_results<I: IntoIterator<Item = QueryResult>>(iter: I) -> QueryResult {
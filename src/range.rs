@@ -1,38 +1,57 @@
+use std::fmt;
+
 use nom::{
     branch::alt,
-    character::complete::{char, i32},
-    combinator::map,
-    sequence::{preceded, separated_pair, terminated},
+    character::complete::{char, i64},
+    combinator::{map, opt, recognize},
+    sequence::{preceded, separated_pair, terminated, tuple},
     IResult,
 };
 
+use serde_json::{json, Value};
+
 use crate::parse::{ParseError, Parseable};
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Range(Option<i32>, Option<i32>);
+pub struct Range(Option<i64>, Option<i64>);
 
 impl Range {
-    pub fn new(bounds: (i32, i32)) -> Self {
+    pub fn new(bounds: (i64, i64)) -> Self {
         Range(Some(bounds.0), Some(bounds.1))
     }
 
-    pub fn lower(i: i32) -> Self {
+    pub fn lower(i: i64) -> Self {
         Range(Some(i), None)
     }
 
-    pub fn upper(i: i32) -> Self {
+    pub fn upper(i: i64) -> Self {
         Range(None, Some(i))
     }
 
+    /// The slice as a path component, which is an object with `start` and `end` bounds where a
+    /// missing bound is `null`.
+    pub fn to_value(&self) -> Value {
+        json!({"start": self.0, "end": self.1})
+    }
+
+    /// Reads a slice path component, rounding fractional bounds outwards as jq does.
+    pub fn from_value(v: &Value) -> Option<Self> {
+        let map = v.as_object()?;
+        if !map.keys().all(|k| k == "start" || k == "end") {
+            return None;
+        }
+        let bound = |key: &str, round: fn(f64) -> f64| match map.get(key) {
+            None | Some(Value::Null) => Some(None),
+            Some(Value::Number(n)) => Some(Some(round(n.as_f64()?) as i64)),
+            Some(_) => None,
+        };
+        Some(Range(bound("start", f64::floor)?, bound("end", f64::ceil)?))
+    }
+
     pub fn normalize(&self, len: usize) -> std::ops::Range<usize> {
-        let normalize_bound = |bound: i32| {
+        let normalize_bound = |bound: i64| {
             if bound < 0 {
-                let u = -bound as usize;
-                if u > len {
-                    0
-                } else {
-                    len - u
-                }
+                len.saturating_sub(bound.unsigned_abs() as usize)
             } else {
                 let u = bound as usize;
                 if u > len {
@@ -44,24 +63,49 @@ impl Range {
         };
 
         match (self.0.map(normalize_bound), self.1.map(normalize_bound)) {
-            (None, None) => unreachable!(),
+            (None, None) => 0..len,
             (None, Some(u)) => 0..u,
             (Some(l), None) => l..len,
-            (Some(l), Some(u)) => l..u,
+            // Inverted bounds select nothing rather than producing an invalid range
+            (Some(l), Some(u)) => l..u.max(l),
         }
     }
 }
 
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(l) = self.0 {
+            write!(f, "{}", l)?;
+        }
+        write!(f, ":")?;
+        if let Some(u) = self.1 {
+            write!(f, "{}", u)?;
+        }
+        Ok(())
+    }
+}
+
 impl Parseable for Range {
     fn parser(input: &str) -> IResult<&str, Range, ParseError> {
         alt((
-            map(separated_pair(i32, char(':'), i32), Range::new),
-            map(preceded(char(':'), i32), Range::upper),
-            map(terminated(i32, char(':')), Range::lower),
+            step,
+            map(separated_pair(i64, char(':'), i64), Range::new),
+            map(preceded(char(':'), i64), Range::upper),
+            map(terminated(i64, char(':')), Range::lower),
         ))(input)
     }
 }
 
+// Fails outright on a step so the error says why, rather than that the index is invalid
+fn step(input: &str) -> IResult<&str, Range, ParseError> {
+    let (_, slice) = recognize(tuple((opt(i64), char(':'), opt(i64), char(':'))))(input)?;
+    let rest = &input[slice.len()..];
+    let end = rest.find(']').unwrap_or(rest.len());
+    Err(nom::Err::Failure(ParseError::StepSlice(
+        input[..slice.len() + end].to_string(),
+    )))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,7 +117,8 @@ mod tests {
         assert_eq!(0..3, Range::new((-100, 3)).normalize(10));
         assert_eq!(1..8, Range::new((1, -2)).normalize(10));
         assert_eq!(0..10, Range::new((-100, 100)).normalize(10));
-        assert_eq!(3..2, Range::new((3, 2)).normalize(10));
+        assert_eq!(3..3, Range::new((3, 2)).normalize(10));
+        assert_eq!(8..8, Range::new((-2, -3)).normalize(10));
         assert_eq!(7..8, Range::new((-3, -2)).normalize(10));
     }
 
@@ -91,6 +136,8 @@ mod tests {
         assert_eq!(0..9, Range::upper(-1).normalize(10));
         assert_eq!(0..10, Range::upper(100).normalize(10));
         assert_eq!(0..0, Range::upper(-100).normalize(10));
+        assert_eq!(0..10, Range::upper(i64::MAX).normalize(10));
+        assert_eq!(0..0, Range::upper(i64::MIN).normalize(10));
     }
 
     #[test]
@@ -114,4 +161,31 @@ mod tests {
             Range::parse("9001:-9001").unwrap()
         );
     }
+
+    #[test]
+    fn parse_step() {
+        for (input, slice) in [("::2", "::2"), ("4:1:-1", "4:1:-1"), ("1::", "1::")] {
+            match Range::parse(input) {
+                Err(ParseError::StepSlice(s)) => assert_eq!(slice, s),
+                r => panic!("expected a step slice error, got {:?}", r),
+            }
+        }
+    }
+    #[test]
+    fn path_component() {
+        let r = Range::new((1, -2));
+        assert_eq!(r#"{"end":-2,"start":1}"#, r.to_value().to_string());
+        assert_eq!(Some(r), Range::from_value(&Range::new((1, -2)).to_value()));
+        assert_eq!(
+            Some(Range::upper(3)),
+            Range::from_value(&serde_json::json!({"start": null, "end": 2.5}))
+        );
+        assert_eq!(
+            Some(Range(None, None)),
+            Range::from_value(&serde_json::json!({}))
+        );
+        assert_eq!(None, Range::from_value(&serde_json::json!({"start": "a"})));
+        assert_eq!(None, Range::from_value(&serde_json::json!({"step": 1})));
+        assert_eq!(None, Range::from_value(&serde_json::json!([1, 2])));
+    }
 }
@@ -1,54 +1,72 @@
+use std::fmt;
+
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while},
-    character::complete::{char, i32},
-    combinator::{map, opt, value},
-    number::complete::float,
-    sequence::delimited,
+    character::complete::{char, digit1, one_of},
+    combinator::{map, map_opt, opt, recognize, value},
+    sequence::{pair, tuple},
     IResult,
 };
 use serde_json::{Number, Value};
 
 use crate::{
-    parse::{ParseError, Parseable},
+    parse::{keyword, ParseError, Parseable},
     query::Executable,
-    single, QueryResult,
+    scope::Scope,
+    single, string, QueryResult,
 };
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Raw(Value);
 
 impl Executable for Raw {
-    fn execute(&self, _: &Value) -> QueryResult {
+    fn execute_in(&self, _: &Value, _: &Scope) -> QueryResult {
         single(self.0.clone())
     }
 }
 
+impl fmt::Display for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl Parseable for Raw {
     fn parser(input: &str) -> IResult<&str, Self, ParseError> {
         map(
             alt((
-                map(
-                    delimited(char('"'), take_while(|c| c != '"'), char('"')),
-                    |s: &str| Value::String(s.to_string()),
-                ),
+                map(string::json_string, Value::String),
                 map(parse_number, Value::Number),
-                value(Value::Null, tag("null")),
+                value(Value::Null, keyword("null")),
+                value(Value::Bool(true), keyword("true")),
+                value(Value::Bool(false), keyword("false")),
             )),
             Raw,
         )(input)
     }
 }
 
+// The whole literal is handed to serde_json so that it's read at full precision
 fn parse_number(input: &str) -> IResult<&str, Number, ParseError> {
-    let (input, i) = i32(input)?;
-    let (input, opt) = opt(float)(input)?;
-    if let Some(n) = opt {
-        let n = (i as f32) + n;
-        Ok((input, Number::from_f64(n as f64).unwrap()))
-    } else {
-        Ok((input, Number::from(i)))
-    }
+    map_opt(
+        recognize(tuple((
+            opt(char('-')),
+            alt((
+                recognize(pair(digit1, opt(pair(char('.'), digit1)))),
+                recognize(pair(char('.'), digit1)),
+            )),
+            opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+        ))),
+        |s: &str| {
+            // JSON requires a leading zero before a bare fraction
+            let json = match s.strip_prefix('-') {
+                Some(abs) if abs.starts_with('.') => format!("-0{}", abs),
+                None if s.starts_with('.') => format!("0{}", s),
+                _ => s.to_string(),
+            };
+            json.parse().ok()
+        },
+    )(input)
 }
 
 #[cfg(test)]
@@ -69,6 +87,25 @@ mod tests {
             Raw(Value::String("f o o".to_string())),
             Raw::parse("\"f o o\"").unwrap()
         );
+        assert_eq!(
+            Raw(Value::String("a\"b\\c\td\u{e9}\u{1f600}".to_string())),
+            Raw::parse(r#""a\"b\\c\td\u00e9\ud83d\ude00""#).unwrap()
+        );
+        assert!(Raw::parse(r#""\q""#).is_err());
+        assert!(Raw::parse(r#""\u12""#).is_err());
+        assert!(Raw::parse(r#""\ud83d""#).is_err());
+        assert!(Raw::parse(r#""a\""#).is_err());
+    }
+
+    #[test]
+    fn parse_raw_literal() {
+        assert!(Raw::parse("nul").is_err());
+        assert!(Raw::parse("nulls").is_err());
+        assert!(Raw::parse("True").is_err());
+
+        assert_eq!(Raw(Value::Null), Raw::parse("null").unwrap());
+        assert_eq!(Raw(Value::Bool(true)), Raw::parse("true").unwrap());
+        assert_eq!(Raw(Value::Bool(false)), Raw::parse("false").unwrap());
     }
 
     #[test]
@@ -85,5 +122,51 @@ mod tests {
             Raw(Value::Number(Number::from_f64(0.5).unwrap())),
             Raw::parse("0.5").unwrap()
         );
+
+        assert!(Raw::parse("1e").is_err());
+        assert!(Raw::parse("1e+").is_err());
+        assert!(Raw::parse("1.").is_err());
+        assert!(Raw::parse(".").is_err());
+        assert!(Raw::parse("-.").is_err());
+
+        assert_eq!(
+            Raw(Value::Number(Number::from(0))),
+            Raw::parse("0").unwrap()
+        );
+        assert_eq!(
+            Raw(Value::Number(Number::from_f64(1000.0).unwrap())),
+            Raw::parse("1e3").unwrap()
+        );
+        assert_eq!(
+            Raw(Value::Number(Number::from_f64(-0.25).unwrap())),
+            Raw::parse("-2.5e-1").unwrap()
+        );
+        assert_eq!(
+            Raw(Value::Number(Number::from_f64(0.5).unwrap())),
+            Raw::parse(".5").unwrap()
+        );
+        assert_eq!(
+            Raw(Value::Number(Number::from_f64(-0.5).unwrap())),
+            Raw::parse("-.5").unwrap()
+        );
+        assert_eq!(
+            Raw(Value::Number(Number::from_f64(120.0).unwrap())),
+            Raw::parse("1.2E+2").unwrap()
+        );
+
+        for literal in &[
+            "0.1",
+            "123456.789",
+            "3.141592653589793",
+            "-2.5",
+            "9007199254740993",
+            "1e3",
+            "-2.5e-1",
+        ] {
+            assert_eq!(
+                Raw(serde_json::from_str(literal).unwrap()),
+                Raw::parse(literal).unwrap()
+            );
+        }
     }
 }
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use nom::{
+    bytes::complete::tag,
+    character::complete::{char, multispace1},
+    sequence::{delimited, preceded, separated_pair, tuple},
+    IResult,
+};
+use serde_json::Value;
+
+use crate::{
+    parse::{keyword, parse_identifier, parse_init, parse_pipe, ParseError, Parseable},
+    query::{Executable, Query},
+    scope::Scope,
+    space, QueryResult,
+};
+
+/// `reduce SOURCE as $NAME (INIT; UPDATE)` folds each result of the source into an accumulator.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Reduce {
+    pub source: Query,
+    pub name: String,
+    pub init: Query,
+    pub update: Query,
+}
+
+impl Executable for Reduce {
+    // The source is consumed one result at a time, so it's never held in full
+    fn execute_in(&self, value: &Value, scope: &Scope) -> QueryResult {
+        let mut res = Vec::new();
+        for init in self.init.execute_in(value, scope)? {
+            let mut acc = init;
+            for item in self.source.execute_iter_in(value.clone(), scope.clone()) {
+                let inner = scope.with(&self.name, item?);
+                // An update producing nothing resets the accumulator
+                acc = self
+                    .update
+                    .execute_in(&acc, &inner)?
+                    .pop()
+                    .unwrap_or(Value::Null);
+            }
+            res.push(acc);
+        }
+        Ok(res)
+    }
+}
+
+impl fmt::Display for Reduce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "reduce {} as ${} ({}; {})",
+            self.source, self.name, self.init, self.update
+        )
+    }
+}
+
+impl Parseable for Reduce {
+    fn parser(input: &str) -> IResult<&str, Self, ParseError> {
+        let (input, (source, name, (init, update))) = tuple((
+            preceded(tuple((keyword("reduce"), multispace1)), parse_init),
+            delimited(
+                tuple((keyword("as"), space::before(char('$')))),
+                parse_identifier,
+                space::before(tag("(")),
+            ),
+            space::after(separated_pair(
+                space::around(parse_pipe),
+                char(';'),
+                space::around(parse_pipe),
+            )),
+        ))(input)?;
+        let (input, _) = char(')')(input)?;
+        Ok((
+            input,
+            Reduce {
+                source,
+                name: name.to_string(),
+                init,
+                update,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{index::Index, raw::Raw};
+
+    use super::*;
+
+    #[test]
+    fn parse_reduce() {
+        assert!(Reduce::parse("reduce .[] as x (0; .)").is_err());
+        assert!(Reduce::parse("reduce .[] as $x (0)").is_err());
+        assert!(Reduce::parse("reduce.[] as $x (0; .)").is_err());
+        assert!(Reduce::parse("reduce .[] $x (0; .)").is_err());
+
+        assert_eq!(
+            Reduce {
+                source: Query::Iterator,
+                name: "item".to_string(),
+                init: Query::Raw(Raw::parse("0").unwrap()),
+                update: Query::Index(Index::String("a".to_string())),
+            },
+            Reduce::parse("reduce .[] as $item ( 0 ; .a )").unwrap()
+        );
+    }
+}
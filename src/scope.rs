@@ -0,0 +1,193 @@
+use std::{cell::RefCell, env, fmt, iter, rc::Rc};
+
+use serde_json::{Map, Value};
+
+use crate::QueryError;
+
+/// Settings that constrain how a query is executed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecOptions {
+    /// Maximum number of objects a single object construction may produce.
+    pub max_combinations: Option<usize>,
+    /// Index mismatched types leniently, so that a key index on a non-object or an integer
+    /// index on a non-array gives `null` rather than an error.
+    pub lenient_index: bool,
+}
+
+/// A stream of input documents shared between its clones, so that the caller reading documents
+/// and the `input` and `inputs` builtins each take the next one from the same place.
+#[derive(Clone)]
+pub struct InputStream(Rc<RefCell<dyn Iterator<Item = Result<Value, QueryError>>>>);
+
+impl InputStream {
+    pub fn new<I: Iterator<Item = Result<Value, QueryError>> + 'static>(inputs: I) -> Self {
+        InputStream(Rc::new(RefCell::new(inputs)))
+    }
+}
+
+impl Iterator for InputStream {
+    type Item = Result<Value, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.borrow_mut().next()
+    }
+}
+
+impl fmt::Debug for InputStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("InputStream")
+    }
+}
+
+impl PartialEq for InputStream {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// A buffer shared between its clones that collects the messages written by `debug` and
+/// `stderr`, so that they can be captured rather than going straight to the process's stderr.
+#[derive(Clone, Default)]
+pub struct Messages(Rc<RefCell<Vec<u8>>>);
+
+impl Messages {
+    pub fn new() -> Self {
+        Messages::default()
+    }
+
+    /// Takes everything written since the last call.
+    pub fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+impl fmt::Debug for Messages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Messages")
+    }
+}
+
+impl PartialEq for Messages {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Variables visible to a query during execution, along with the execution options, the
+/// stream of remaining inputs and where messages are written.
+///
+/// The variables are shared between clones, and binding one only links it in front of those
+/// already bound, so neither cloning a scope nor binding a variable copies any values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    variables: Option<Rc<Binding>>,
+    options: ExecOptions,
+    inputs: Option<InputStream>,
+    messages: Option<Messages>,
+}
+
+/// A variable, linked to the bindings made before it. Later bindings shadow earlier ones.
+#[derive(Debug, PartialEq)]
+struct Binding {
+    name: String,
+    value: Value,
+    parent: Option<Rc<Binding>>,
+}
+
+impl Scope {
+    /// Creates the root scope for an execution, reading the process environment into `$ENV`.
+    pub fn new() -> Self {
+        let env: Map<String, Value> = env::vars().map(|(k, v)| (k, Value::String(v))).collect();
+        Scope {
+            variables: None,
+            options: ExecOptions::default(),
+            inputs: None,
+            messages: None,
+        }
+        .with("ENV", Value::Object(env))
+    }
+
+    pub fn with_options(mut self, options: ExecOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_variables<I: IntoIterator<Item = (String, Value)>>(self, variables: I) -> Self {
+        variables
+            .into_iter()
+            .fold(self, |scope, (name, value)| scope.with(&name, value))
+    }
+
+    pub fn with_inputs(mut self, inputs: InputStream) -> Self {
+        self.inputs = Some(inputs);
+        self
+    }
+
+    /// Takes the next document from the input stream, if there is one and it isn't exhausted.
+    pub(crate) fn next_input(&self) -> Option<Result<Value, QueryError>> {
+        self.inputs.clone()?.next()
+    }
+
+    pub fn with_messages(mut self, messages: Messages) -> Self {
+        self.messages = Some(messages);
+        self
+    }
+
+    /// Writes a message for `debug` or `stderr`, to the process's stderr if they aren't being
+    /// captured.
+    pub(crate) fn message(&self, text: &str) {
+        match &self.messages {
+            Some(m) => m.0.borrow_mut().extend_from_slice(text.as_bytes()),
+            None => eprint!("{}", text),
+        }
+    }
+
+    pub fn options(&self) -> &ExecOptions {
+        &self.options
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let mut bindings = iter::successors(self.variables.as_deref(), |b| b.parent.as_deref());
+        bindings.find(|b| b.name == name).map(|b| &b.value)
+    }
+
+    /// Returns a child scope with an additional variable bound.
+    pub fn with(&self, name: &str, value: Value) -> Self {
+        let binding = Binding {
+            name: name.to_string(),
+            value,
+            parent: self.variables.clone(),
+        };
+        Scope {
+            variables: Some(Rc::new(binding)),
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings() {
+        let root = Scope::new().with_variables(vec![("a".to_string(), Value::from(1))]);
+        let child = root.with("a", Value::from(2)).with("b", Value::from(3));
+        assert_eq!(Some(&Value::from(2)), child.get("a"));
+        assert_eq!(Some(&Value::from(3)), child.get("b"));
+        assert_eq!(Some(&Value::from(1)), root.get("a"));
+        assert_eq!(None, root.get("b"));
+        assert!(child.get("ENV").unwrap().is_object());
+
+        // The bindings already made are shared rather than copied
+        let (a, b) = (root.with("x", Value::Null), root.with("y", Value::Null));
+        let parent = |s: &Scope| s.variables.as_ref().unwrap().parent.clone().unwrap();
+        assert!(Rc::ptr_eq(&parent(&a), &parent(&b)));
+    }
+}
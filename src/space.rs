@@ -1,13 +1,31 @@
-use nom::{character::complete::space0, IResult};
+use nom::{
+    character::complete::{char, multispace0, not_line_ending},
+    combinator::value,
+    multi::many0_count,
+    sequence::{pair, tuple},
+    IResult,
+};
 
 use crate::parse::ParseError;
 
+/// Skips whitespace, including newlines, along with any `#` comments, which run to the end of
+/// their line.
+pub(crate) fn ws(input: &str) -> IResult<&str, (), ParseError> {
+    value(
+        (),
+        pair(
+            multispace0,
+            many0_count(tuple((char('#'), not_line_ending, multispace0))),
+        ),
+    )(input)
+}
+
 pub(crate) fn before<'a, F, O>(mut f: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, ParseError>
 where
     F: FnMut(&'a str) -> IResult<&'a str, O, ParseError>,
 {
     move |input: &'a str| {
-        let (input, _) = space0(input)?;
+        let (input, _) = ws(input)?;
         f(input)
     }
 }
@@ -18,7 +36,7 @@ where
 {
     move |input: &'a str| {
         let (input, o) = f(input)?;
-        let (input, _) = space0(input)?;
+        let (input, _) = ws(input)?;
         Ok((input, o))
     }
 }
@@ -0,0 +1,145 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while1, take_while_m_n},
+    character::complete::char,
+    combinator::{map, map_opt, success, value, verify},
+    multi::fold_many0,
+    sequence::{delimited, preceded},
+    IResult,
+};
+use serde_json::Value;
+
+use crate::parse::ParseError;
+
+/// Parses a double-quoted string, unescaping it as JSON does.
+pub(crate) fn json_string(input: &str) -> IResult<&str, String, ParseError> {
+    delimited(
+        char('"'),
+        fold_many0(
+            alt((map(unescaped, Piece::Text), map(escaped, Piece::Char))),
+            String::new,
+            |mut s, piece| {
+                match piece {
+                    Piece::Text(t) => s.push_str(t),
+                    Piece::Char(c) => s.push(c),
+                }
+                s
+            },
+        ),
+        char('"'),
+    )(input)
+}
+
+enum Piece<'a> {
+    Text(&'a str),
+    Char(char),
+}
+
+/// Matches a run of string contents that needs no unescaping.
+pub(crate) fn unescaped(input: &str) -> IResult<&str, &str, ParseError> {
+    take_while1(|c| c != '"' && c != '\\')(input)
+}
+
+/// Parses a single JSON escape sequence, including the leading backslash.
+pub(crate) fn escaped(input: &str) -> IResult<&str, char, ParseError> {
+    preceded(
+        char('\\'),
+        alt((
+            value('"', char('"')),
+            value('\\', char('\\')),
+            value('/', char('/')),
+            value('\u{8}', char('b')),
+            value('\u{c}', char('f')),
+            value('\n', char('n')),
+            value('\r', char('r')),
+            value('\t', char('t')),
+            preceded(char('u'), unicode),
+        )),
+    )(input)
+}
+
+/// Escapes the contents of a string so it can be written back between double quotes.
+pub(crate) fn escape(s: &str) -> String {
+    let quoted = Value::from(s).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+// Characters outside the BMP are escaped as a UTF-16 surrogate pair
+fn unicode(input: &str) -> IResult<&str, char, ParseError> {
+    let (rest, high) = hex4(input)?;
+    if !(0xD800..0xDC00).contains(&high) {
+        return map_opt(success(high), char::from_u32)(rest);
+    }
+    map_opt(
+        preceded(
+            tag("\\u"),
+            verify(hex4, |low| (0xDC00..0xE000).contains(low)),
+        ),
+        move |low| char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)),
+    )(rest)
+}
+
+fn hex4(input: &str) -> IResult<&str, u32, ParseError> {
+    map_opt(take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit()), |s| {
+        u32::from_str_radix(s, 16).ok()
+    })(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use nom::combinator::all_consuming;
+
+    use super::*;
+
+    fn parse(input: &str) -> Option<String> {
+        all_consuming(json_string)(input).ok().map(|(_, s)| s)
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(Some("".to_string()), parse(r#""""#));
+        assert_eq!(Some("f o o".to_string()), parse(r#""f o o""#));
+        assert_eq!(Some("é😀".to_string()), parse(r#""é😀""#));
+
+        assert_eq!(None, parse("foo"));
+        assert_eq!(None, parse(r#""foo"#));
+        assert_eq!(None, parse(r#"foo""#));
+    }
+
+    #[test]
+    fn escapes() {
+        for (input, expected) in &[
+            (r#""\"""#, "\""),
+            (r#""\\""#, "\\"),
+            (r#""\/""#, "/"),
+            (r#""\b\f\n\r\t""#, "\u{8}\u{c}\n\r\t"),
+            (r#""a\"b\\c""#, "a\"b\\c"),
+            (r#""\u00e9\u00E9""#, "éé"),
+            (r#""\ud83d\ude00""#, "😀"),
+        ] {
+            assert_eq!(Some(expected.to_string()), parse(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        for input in &[
+            r#""\q""#,
+            r#""\""#,
+            r#""\u12""#,
+            r#""\u12g4""#,
+            r#""\ud83d""#,
+            r#""\ud83dA""#,
+            r#""\ude00""#,
+        ] {
+            assert_eq!(None, parse(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for s in &["", "plain", "a\"b\\c", "tab\tnew\nline", "\u{1}é😀"] {
+            assert_eq!(Some(s.to_string()), parse(&format!("\"{}\"", escape(s))));
+        }
+    }
+}